@@ -0,0 +1,21 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mpp::{AssembleOptions, Assembly, Lexer};
+
+const SNIPPET: &str = "start: mov al, 'a'\nloop: inc al\nmov out0, al\njz start\njmp loop";
+
+fn assemble_snippets(c: &mut Criterion) {
+    let options = AssembleOptions::default();
+    c.bench_function("scan on a thread", |b| {
+        b.iter(|| Assembly::assemble(black_box(SNIPPET).into()).unwrap())
+    });
+    c.bench_function("scan through a channel", |b| {
+        b.iter(|| Assembly::assemble_sync(black_box(SNIPPET)).unwrap())
+    });
+    let mut lexer = Lexer::new();
+    c.bench_function("reused lexer", |b| {
+        b.iter(|| Assembly::assemble_with_lexer(black_box(SNIPPET), &mut lexer, &options).unwrap())
+    });
+}
+
+criterion_group!(benches, assemble_snippets);
+criterion_main!(benches);
@@ -1,6 +1,18 @@
-use crate::{error::AssemblyError, lexer, parser};
+use crate::{
+    error::{AssemblyError, AssemblyErrors, AssemblyWarning},
+    intel::{
+        disassembly::{self, Decoded, DecodedKind},
+        token::{Token, TokenKind},
+    },
+    interp, lexer, parser, AssembleOptions, DetailedOutput, ErrorCode, Instruction, IsaTable,
+    Lexer, Mnemonic, Primitive,
+};
 use std::{
+    collections::{HashMap, HashSet},
     fmt, fs,
+    hash::{Hash, Hasher},
+    io,
+    ops::Range,
     path::{Path, PathBuf},
     sync::mpsc,
     thread,
@@ -9,39 +21,628 @@ use std::{
 pub struct Assembly {
     data: Vec<u8>,
     path: Option<PathBuf>,
+    source: Option<PathBuf>,
+    source_map: Vec<(usize, Range<u16>)>,
+    labels: HashMap<String, u16>,
+    warnings: Vec<AssemblyWarning>,
+    gaps: Vec<Range<u16>>,
+    relocations: Vec<usize>,
+    hex_case: HexCase,
+    logisim_ending: LogisimEnding,
+    line_ending: Option<LineEnding>,
+    segments: Vec<Segment>,
+    /// Whether each byte belongs to an instruction, as opposed to data
+    code_map: Vec<bool>,
+    /// What was added to every label's address
+    base_address: u16,
+    /// Whether the byte code was turned into another format
+    transformed: bool,
+    /// The first misuse of the output chain, reported by `finish`
+    misuse: Option<Misuse>,
+}
+
+/// Ways the output chain may be misused, kept apart from [`ErrorCode`] since
+/// it is not `Copy`.
+#[derive(Copy, Clone, Debug)]
+enum Misuse {
+    RepeatedTransform,
+    TransformAfterSave,
+    MapAfterTransform,
+}
+
+/// A run of bytes assembled one after the other, in between `.org` gaps.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub start: u16,
+    pub len: usize,
+}
+
+/// The first byte an assembled source differs from its expected image at, as
+/// found by [`Assembly::verify_against`]. A byte is `None` past the end of
+/// its image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub offset: usize,
+    pub expected: Option<u8>,
+    pub actual: Option<u8>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let byte = |byte: Option<u8>| match byte {
+            Some(byte) => format!("{:#04x}", byte),
+            None => "the end of the image".into(),
+        };
+        write!(
+            f,
+            "expected {} at offset {}, but found {}",
+            byte(self.expected),
+            self.offset,
+            byte(self.actual)
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// The base in which numbers are written in text outputs.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Radix {
+    Binary,
+    Decimal,
+    #[default]
+    Hexadecimal,
+}
+
+impl Radix {
+    fn format_byte(self, byte: u8, case: HexCase) -> String {
+        match self {
+            Self::Binary => format!("{:08b}", byte),
+            Self::Decimal => format!("{:03}", byte),
+            Self::Hexadecimal => {
+                let (hi, lo) = byte_as_hexadecimal(byte, case);
+                format!("{}{}", hi as char, lo as char)
+            }
+        }
+    }
+
+    fn format_address(self, address: u16, case: HexCase) -> String {
+        match self {
+            Self::Binary => format!("{:016b}", address),
+            Self::Decimal => format!("{:05}", address),
+            Self::Hexadecimal if case == HexCase::Upper => format!("{:04X}", address),
+            Self::Hexadecimal => format!("{:04x}", address),
+        }
+    }
+
+    pub(crate) fn format_number(self, num: usize, case: HexCase) -> String {
+        match self {
+            Self::Binary => format!("{:b}", num),
+            Self::Decimal => num.to_string(),
+            Self::Hexadecimal if case == HexCase::Upper => format!("{:X}", num),
+            Self::Hexadecimal => format!("{:x}", num),
+        }
+    }
+}
+
+/// The case of the digits `a` through `f` in hexadecimal text outputs.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum HexCase {
+    #[default]
+    Lower,
+    Upper,
+}
+
+/// How a Logisim memory image ends after its last byte.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum LogisimEnding {
+    /// A space, as every other byte is followed by
+    #[default]
+    Space,
+    /// A line break in place of the last space, as some tools expect text
+    /// files to end with
+    Newline,
+}
+
+/// The line break written by the text outputs of the assembly.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineEnding {
+    /// `\n`, as usual on Unix
+    Lf,
+    /// `\r\n`, as usual on Windows, and as Logisim writes its images
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Whether a source path should be resolved through symbolic links before
+/// being recorded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FollowSymlinks {
+    /// Record the canonical path of the source, with every link resolved
+    Yes,
+    /// Record the source path as it was given
+    No,
+}
+
+/// The kinds of files an `Assembly` may be written out as.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// The raw byte code
+    Binary,
+    /// The byte code as a Logisim memory image, as made by
+    /// [`Assembly::to_logisim`](Assembly::to_logisim)
+    Logisim,
+    /// The source along with its byte code, as made by
+    /// [`Assembly::to_listing`](Assembly::to_listing)
+    Listing,
+}
+
+impl OutputFormat {
+    /// The extension files of this format are given.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Binary => "bin",
+            Self::Logisim => "hex",
+            Self::Listing => "lst",
+        }
+    }
+}
+
+/// The path an output of `format` is written to when none is given: the
+/// `input` path with the format's extension. Should that be the input itself,
+/// the extension is appended instead, so the source is never overwritten.
+pub fn default_output_path(input: &Path, format: OutputFormat) -> PathBuf {
+    let path = input.with_extension(format.extension());
+    if path == input {
+        let mut name = input.as_os_str().to_os_string();
+        name.push(".");
+        name.push(format.extension());
+        PathBuf::from(name)
+    } else {
+        path
+    }
 }
 
 impl Assembly {
     pub fn assemble(src: String) -> Result<Self, AssemblyError> {
+        Self::assemble_with(src, &AssembleOptions::default())
+    }
+
+    pub fn assemble_with(src: String, options: &AssembleOptions) -> Result<Self, AssemblyError> {
+        Self::assemble_with_progress_opts(src, options, |_, _| ())
+    }
+
+    /// Assembles `src`, calling `progress` at the end of every line with its
+    /// number and the amount of bytes emitted so far.
+    pub fn assemble_with_progress(
+        src: String,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<Self, AssemblyError> {
+        Self::assemble_with_progress_opts(src, &AssembleOptions::default(), progress)
+    }
+
+    pub fn assemble_with_progress_opts(
+        src: String,
+        options: &AssembleOptions,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<Self, AssemblyError> {
         let (sender, receiver) = mpsc::channel();
         let lexer = thread::spawn(move || lexer::scan(&src, sender));
-        let data = parser::eval(receiver)?;
+        let output = parser::eval_with_progress(receiver, options, progress);
+        // Parser errors may be a consequence of a token the lexer could not
+        // form, so the lexer's own errors come first
         lexer.join().expect("lexer stopped unexpectedly")?;
-        Ok(Assembly { data, path: None })
+        Self::from_output(output?, options)
+    }
+
+    /// Assembles `src` without spawning a thread for the lexer, for targets
+    /// where threads are not available, such as WASM.
+    pub fn assemble_sync(src: &str) -> Result<Self, AssemblyError> {
+        Self::assemble_sync_with(src, &AssembleOptions::default())
+    }
+
+    pub fn assemble_sync_with(src: &str, options: &AssembleOptions) -> Result<Self, AssemblyError> {
+        let (sender, receiver) = mpsc::channel();
+        // The whole source is tokenized upfront, so lexer errors are found
+        // before the parser ever runs
+        lexer::scan(src, sender)?;
+        Self::from_output(parser::eval(receiver, options)?, options)
+    }
+
+    /// Assembles `src` on the current thread, writing the byte code straight
+    /// after the bytes in `buf` instead of making an `Assembly` of it, so that
+    /// its capacity is reused. The bytes are the same
+    /// [`assemble`](Self::assemble) yields, and `buf` is left as it was on
+    /// error.
+    pub fn assemble_into(src: &str, buf: &mut Vec<u8>) -> Result<(), AssemblyError> {
+        let (sender, receiver) = mpsc::channel();
+        lexer::scan(src, sender)?;
+        parser::eval_into(receiver, &AssembleOptions::default(), buf)?;
+        Ok(())
+    }
+
+    /// Assembles `src` on the current thread, tokenizing it with `lexer`, so
+    /// that assembling many small sources does not allocate anew for each.
+    pub fn assemble_with_lexer(
+        src: &str,
+        lexer: &mut Lexer,
+        options: &AssembleOptions,
+    ) -> Result<Self, AssemblyError> {
+        let tokens = lexer.scan(src)?;
+        Self::from_output(parser::eval(tokens.drain(..), options)?, options)
+    }
+
+    /// Assembles `src` into its bytes, every instruction as it was built,
+    /// and the address of every label, saving a disassembly pass when both
+    /// the bytes and the instructions are needed.
+    pub fn assemble_detailed(
+        src: &str,
+        options: &AssembleOptions,
+    ) -> Result<DetailedOutput, AssemblyError> {
+        let mut lexer = Lexer::new();
+        let tokens = lexer.scan(src)?;
+        parser::eval_detailed(tokens.drain(..), options)
+    }
+
+    /// Assembles `src` and compares its byte code against `expected`, as for
+    /// golden file tests. The outer error is that of a source that failed to
+    /// assemble, and the inner one the first byte that differs.
+    pub fn verify_against(
+        src: &str,
+        expected: &[u8],
+    ) -> Result<Result<(), Mismatch>, AssemblyError> {
+        let assembly = Self::assemble(src.into())?;
+        let actual = assembly.as_byte_code();
+        let len = actual.len().max(expected.len());
+        let mismatch = (0..len)
+            .find(|&offset| actual.get(offset) != expected.get(offset))
+            .map(|offset| Mismatch {
+                offset,
+                expected: expected.get(offset).copied(),
+                actual: actual.get(offset).copied(),
+            });
+        Ok(mismatch.map_or(Ok(()), Err))
+    }
+
+    /// Every token `src` is made of, comments included, for seeing how the
+    /// lexer split it. Where a token could not be formed, a
+    /// [`TokenKind::Error`] covers its text, and the rest of its line is
+    /// skipped.
+    pub fn dump_tokens(src: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new().keep_comments(true);
+        let mut errors = Vec::new();
+        let mut tokens: Vec<Token> = lexer
+            .scan_recovering(src, &mut errors)
+            .drain(..)
+            .filter_map(Result::ok)
+            .collect();
+        tokens.extend(errors.into_iter().map(|err| Token {
+            kind: TokenKind::Error,
+            ..err.token
+        }));
+        tokens.sort_by_key(|token| (token.line, token.span.start));
+        tokens
+    }
+
+    /// Assembles `src`, carrying on after errors so as to report as many as
+    /// possible, up to `max_errors`. Parser errors on lines the lexer already
+    /// failed on are left out, as they likely stem from it.
+    pub fn assemble_collecting_errors(
+        src: &str,
+        options: &AssembleOptions,
+        max_errors: usize,
+    ) -> Result<Self, AssemblyErrors> {
+        let mut lexer = Lexer::new();
+        let mut errors = Vec::new();
+        let tokens = lexer.scan_recovering(src, &mut errors);
+        let lexer_lines: Vec<usize> = errors.iter().map(|err| err.token.line).collect();
+        let mut parser_errors = Vec::new();
+        let output = parser::eval_recovering(tokens.drain(..), options, &mut parser_errors);
+        errors.extend(
+            parser_errors
+                .into_iter()
+                .filter(|err| !lexer_lines.contains(&err.token.line)),
+        );
+        if let Some(mut output) = output.filter(|_| errors.is_empty()) {
+            errors = output.promote_warnings(options);
+            if errors.is_empty() {
+                return Ok(Self::from_output(output, options)?);
+            }
+        }
+        errors.sort_by_key(|err| err.token.line);
+        let overflow = errors.len().saturating_sub(max_errors);
+        errors.truncate(max_errors);
+        Err(AssemblyErrors { errors, overflow })
+    }
+
+    pub(crate) fn from_output(
+        mut output: parser::Output,
+        options: &AssembleOptions,
+    ) -> Result<Self, AssemblyError> {
+        // The warnings are all collected by `assemble_collecting_errors`
+        if let Some(err) = output.promote_warnings(options).into_iter().next() {
+            return Err(err);
+        }
+        Ok(Assembly {
+            path: None,
+            source: None,
+            source_map: output.source_map,
+            labels: output.labels,
+            warnings: output.warnings,
+            gaps: output.gaps,
+            relocations: output.relocations,
+            hex_case: options.hex_case,
+            logisim_ending: options.logisim_ending,
+            line_ending: options.line_ending,
+            code_map: code_map(output.data.len(), &output.code),
+            data: output.data,
+            segments: output.segments,
+            base_address: options.base_address,
+            transformed: false,
+            misuse: None,
+        })
     }
 
     pub fn from_path<P>(path: P) -> Result<Self, AssemblyError>
     where
         P: AsRef<Path>,
     {
-        Self::assemble(fs::read_to_string(path).unwrap())
+        Self::from_path_opts(path, FollowSymlinks::No)
+    }
+
+    pub fn from_path_opts<P>(path: P, follow: FollowSymlinks) -> Result<Self, AssemblyError>
+    where
+        P: AsRef<Path>,
+    {
+        // There is no line to point at in a source that could not be read
+        let io_error = |err| {
+            let token = Token {
+                kind: TokenKind::Error,
+                span: 0..0,
+                line: 0,
+            };
+            AssemblyError::new(token, ErrorCode::Io(err))
+        };
+        let path = match follow {
+            FollowSymlinks::Yes => fs::canonicalize(path).map_err(io_error)?,
+            FollowSymlinks::No => path.as_ref().to_path_buf(),
+        };
+        let src = fs::read_to_string(&path).map_err(io_error)?;
+        let mut assembly = Self::assemble(src)?;
+        assembly.source = Some(path);
+        Ok(assembly)
+    }
+
+    /// The path this `Assembly` was read from, if any.
+    pub fn source_path(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// Every label and its address, ordered by address and then by name, as to
+    /// always be listed in the same order.
+    pub fn symbols(&self) -> Vec<(&str, u16)> {
+        let mut symbols = self
+            .labels
+            .iter()
+            .map(|(label, &address)| (label.as_str(), address))
+            .collect::<Vec<_>>();
+        symbols.sort_unstable_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        symbols
+    }
+
+    /// The address ranges skipped over by `.org` which are not part of the
+    /// image, as requested by [`Gaps::Sparse`](crate::Gaps::Sparse). Gaps
+    /// filled in are part of the image, so they are not listed.
+    pub fn gaps(&self) -> &[Range<u16>] {
+        &self.gaps
+    }
+
+    /// Every run of bytes assembled in between the gaps left by `.org`, in the
+    /// order they appear.
+    pub fn segments(&self) -> Vec<Segment> {
+        self.segments.clone()
+    }
+
+    /// Removes the run of `byte` the byte code ends with, such as the padding
+    /// left by a trailing `.org`. Instructions and label addresses are never
+    /// cut into, and neither is anything in between them.
+    pub fn strip_trailing(&mut self, byte: u8) -> &mut Self {
+        let keep = self
+            .code_map
+            .iter()
+            .rposition(|&is_code| is_code)
+            .map(|last| last + 1)
+            .into_iter()
+            .chain(self.relocations.iter().map(|&offset| offset + 2))
+            .max()
+            .unwrap_or(0);
+        let mut len = self.data.len();
+        while len > keep && self.data[len - 1] == byte {
+            len -= 1;
+        }
+        self.data.truncate(len);
+        self.code_map.truncate(len);
+        let end = len as u16;
+        let clamp = |range: &mut Range<u16>| {
+            range.end = range.end.min(end);
+            range.start < range.end
+        };
+        self.source_map.retain_mut(|(_, range)| clamp(range));
+        self.gaps.retain_mut(clamp);
+        self.segments.retain_mut(|segment| {
+            segment.len = segment.len.min(len.saturating_sub(segment.start as usize));
+            segment.len > 0
+        });
+        self
+    }
+
+    /// Splits the byte code into a ROM image with the instructions and a RAM
+    /// image with the data, for targets that keep them apart. Both images
+    /// keep the addresses of the whole program, so the bytes of the other
+    /// image are zeroed, and each ends with its own last byte. Gaps are left
+    /// out of both.
+    pub fn split_rom_ram(&self) -> (Vec<u8>, Vec<u8>) {
+        let is_code = &self.code_map;
+        let (mut rom, mut ram) = (Vec::new(), Vec::new());
+        for segment in self.segments() {
+            let bytes = self.data.iter().zip(is_code).enumerate();
+            for (address, (&byte, &is_code)) in bytes.skip(segment.start as usize).take(segment.len)
+            {
+                let image = if is_code { &mut rom } else { &mut ram };
+                image.resize(address, 0);
+                image.push(byte);
+            }
+        }
+        (rom, ram)
+    }
+
+    /// The offsets of every label address written into the byte code. Loading
+    /// the byte code at another base address requires adding it to the
+    /// big-endian word at each of these.
+    pub fn relocations(&self) -> &[usize] {
+        &self.relocations
+    }
+
+    /// Runs the program in the [reference interpreter](crate::run), loaded at
+    /// its base address, for `max_steps` instructions at most.
+    pub fn run(&self, max_steps: usize) -> interp::MachineState {
+        interp::run_with(
+            &self.data,
+            self.base_address,
+            &IsaTable::default(),
+            max_steps,
+        )
+    }
+
+    /// The likely mistakes found while assembling.
+    pub fn warnings(&self) -> &[AssemblyWarning] {
+        &self.warnings
+    }
+
+    /// The line break text outputs are written with: the one chosen through
+    /// [`AssembleOptions::line_ending`], or else the `native` one of the
+    /// format.
+    fn line_break(&self, native: LineEnding) -> &'static str {
+        self.line_ending.unwrap_or(native).as_str()
+    }
+
+    /// Notes that the byte code is being turned into another format, along
+    /// with whether that misuses the output chain.
+    fn mark_transformed(&mut self) {
+        if self.transformed {
+            self.misuse.get_or_insert(Misuse::RepeatedTransform);
+        } else if self.path.is_some() {
+            self.misuse.get_or_insert(Misuse::TransformAfterSave);
+        }
+        self.transformed = true;
+    }
+
+    /// Replaces every byte with what `f` returns for its offset and value,
+    /// as to scramble the image before it is saved. The bytes are the ones
+    /// assembled, so this goes before any transform in the output chain.
+    pub fn map_bytes(&mut self, mut f: impl FnMut(usize, u8) -> u8) -> &mut Self {
+        if self.transformed {
+            self.misuse.get_or_insert(Misuse::MapAfterTransform);
+        }
+        for (offset, byte) in self.data.iter_mut().enumerate() {
+            *byte = f(offset, *byte);
+        }
+        self
     }
 
     pub fn to_logisim(&mut self) -> &mut Self {
+        self.mark_transformed();
         let mut vec = Vec::with_capacity(self.data.len() * 3 + 10);
-        vec.extend_from_slice(b"v2.0 raw\r\n");
+        let line_break = self.line_break(LineEnding::CrLf);
+        vec.extend_from_slice(b"v2.0 raw");
+        vec.extend_from_slice(line_break.as_bytes());
         for &byte in &self.data {
-            let (hi, lo) = byte_as_hexadecimal(byte);
+            let (hi, lo) = byte_as_hexadecimal(byte, self.hex_case);
             if hi != b'0' {
                 vec.push(hi as u8);
             }
             vec.push(lo as u8);
             vec.push(0x20);
         }
+        if self.logisim_ending == LogisimEnding::Newline && !self.data.is_empty() {
+            vec.pop();
+            vec.extend_from_slice(line_break.as_bytes());
+        }
         self.data = vec;
         self
     }
 
+    /// Turns the byte code into a Logisim-Evolution `v3.0 hex words addressed`
+    /// image, where every line starts with the address of its first byte, so
+    /// the gaps left by `.org` are simply skipped over. Lines hold up to 16
+    /// bytes, and a new one starts after every gap.
+    pub fn to_logisim_addressed(&mut self) -> &mut Self {
+        self.mark_transformed();
+        let line_break = self.line_break(LineEnding::CrLf);
+        let mut text = String::from("v3.0 hex words addressed");
+        text.push_str(line_break);
+        for segment in self.segments() {
+            let start = segment.start as usize;
+            let bytes = &self.data[start..start + segment.len];
+            for (row, chunk) in bytes.chunks(16).enumerate() {
+                let address = self.base_address as usize + start + row * 16;
+                text.push_str(&match self.hex_case {
+                    HexCase::Lower => format!("{:04x}:", address),
+                    HexCase::Upper => format!("{:04X}:", address),
+                });
+                for &byte in chunk {
+                    let (hi, lo) = byte_as_hexadecimal(byte, self.hex_case);
+                    text.push(' ');
+                    text.push(hi as char);
+                    text.push(lo as char);
+                }
+                text.push_str(line_break);
+            }
+        }
+        self.data = text.into_bytes();
+        self
+    }
+
+    /// Writes the address of the `target` label at `at`, as to make a reset
+    /// vector point to it.
+    pub fn with_reset_vector(&mut self, at: u16, target: &str) -> Result<&mut Self, ErrorCode> {
+        let address = match self.labels.get(target) {
+            Some(address) => address.to_be_bytes(),
+            None => return Err(ErrorCode::UnknownLabel(target.into())),
+        };
+        let at = at as usize;
+        match self.data.get_mut(at..at + 2) {
+            Some(slot) => slot.copy_from_slice(&address),
+            None => return Err(ErrorCode::AddressOutOfRange(at, self.data.len())),
+        }
+        if let Err(idx) = self.relocations.binary_search(&at) {
+            self.relocations.insert(idx, at);
+        }
+        Ok(self)
+    }
+
+    /// Writes the address of the `value_label` label into the two bytes found
+    /// at the `at_label` label, such as a slot reserved with `.dw 0`.
+    pub fn patch_word(
+        &mut self,
+        at_label: &str,
+        value_label: &str,
+    ) -> Result<&mut Self, ErrorCode> {
+        let at = match self.labels.get(at_label) {
+            Some(&address) => address - self.base_address,
+            None => return Err(ErrorCode::UnknownLabel(at_label.into())),
+        };
+        self.with_reset_vector(at, value_label)
+    }
+
     pub fn then_save_as<P>(&mut self, path: P) -> &mut Self
     where
         P: AsRef<Path>,
@@ -55,6 +656,209 @@ impl Assembly {
         self
     }
 
+    /// Ends the output chain, writing the byte code to the save path, as long
+    /// as it was transformed at most once and before the path was set.
+    pub fn finish(&mut self) -> Result<(), ErrorCode> {
+        match self.misuse {
+            Some(Misuse::RepeatedTransform) => return Err(ErrorCode::RepeatedTransform),
+            Some(Misuse::TransformAfterSave) => return Err(ErrorCode::TransformAfterSave),
+            Some(Misuse::MapAfterTransform) => return Err(ErrorCode::MapAfterTransform),
+            None => (),
+        }
+        let path = self.path.take().ok_or(ErrorCode::NoSavePath)?;
+        fs::write(path, &self.data)?;
+        Ok(())
+    }
+
+    /// Renders the byte code in the style of `xxd`: the offset, followed by
+    /// up to 16 bytes in hexadecimal, and their ASCII representation, with
+    /// non-printable bytes shown as `.`.
+    pub fn hexdump(&self) -> String {
+        let mut dump = String::new();
+        for (row, chunk) in self.data.chunks(16).enumerate() {
+            dump.push_str(&match self.hex_case {
+                HexCase::Lower => format!("{:08x}:", row * 16),
+                HexCase::Upper => format!("{:08X}:", row * 16),
+            });
+            for (col, &byte) in chunk.iter().enumerate() {
+                if col % 2 == 0 {
+                    dump.push(' ');
+                }
+                let (hi, lo) = byte_as_hexadecimal(byte, self.hex_case);
+                dump.push(hi as char);
+                dump.push(lo as char);
+            }
+            // Pad short rows so the ASCII column always lines up
+            let missing = 16 - chunk.len();
+            dump.push_str(&" ".repeat(missing * 2 + missing / 2 + 2));
+            for &byte in chunk {
+                dump.push(if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                });
+            }
+            dump.push_str(self.line_break(LineEnding::Lf));
+        }
+        dump
+    }
+
+    /// Renders a listing of `src`, which must be the source this `Assembly`
+    /// was assembled from, with every line prefixed by the address and the
+    /// bytes it was translated into, written in `radix`.
+    pub fn to_listing(&self, src: &str, radix: Radix) -> String {
+        let mut columns = Vec::new();
+        let mut map = self.source_map.iter().peekable();
+        for (line_no, line) in src.lines().enumerate() {
+            match map.next_if(|(line, _)| *line == line_no + 1) {
+                Some((_, range)) => {
+                    let bytes = self.data[range.start as usize..range.end as usize]
+                        .iter()
+                        .map(|&byte| radix.format_byte(byte, self.hex_case))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let address = radix.format_address(range.start, self.hex_case);
+                    columns.push((address, bytes, line));
+                }
+                None => columns.push((String::new(), String::new(), line)),
+            }
+        }
+        let addr_width = radix.format_address(0, self.hex_case).len();
+        let bytes_width = columns.iter().map(|(_, bytes, _)| bytes.len()).max();
+        let mut listing = String::new();
+        for (address, bytes, line) in columns {
+            let row = format!(
+                "{:addr_width$}  {:bytes_width$}  {}",
+                address,
+                bytes,
+                line,
+                addr_width = addr_width,
+                bytes_width = bytes_width.unwrap_or(0)
+            );
+            listing.push_str(row.trim_end());
+            listing.push_str(self.line_break(LineEnding::Lf));
+        }
+        listing
+    }
+
+    /// Reads the byte code back as statements, according to the default
+    /// [`IsaTable`]. See [`disassemble`](crate::disassemble) for other tables.
+    pub fn disassemble(&self) -> Vec<Decoded> {
+        disassembly::disassemble(&self.data, &IsaTable::default())
+    }
+
+    /// Counts the decoder page turns the instructions take, which cost a
+    /// cycle each. Data bytes that happen to be `0x07` are not counted.
+    pub fn page_turn_count(&self) -> usize {
+        self.statements()
+            .iter()
+            .filter(|decoded| matches!(decoded.kind, DecodedKind::Instruction(..)))
+            .map(|decoded| {
+                let start = decoded.address as usize;
+                let page_turn = Instruction::DECODER_PAGE_TURN;
+                self.data[start..start + decoded.len]
+                    .iter()
+                    .take_while(|&&byte| byte == page_turn)
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Whether the byte at `offset` belongs to an instruction, as opposed to
+    /// data, so that a `.db 7` is not taken for a decoder page turn. Offsets
+    /// past the end, and bytes written through [`io::Write`], are data.
+    pub fn is_code_byte(&self, offset: usize) -> bool {
+        self.code_map.get(offset).copied().unwrap_or(false)
+    }
+
+    /// Decodes the instructions in between `.org` gaps, leaving the data as
+    /// single bytes.
+    fn statements(&self) -> Vec<Decoded> {
+        let isa = IsaTable::default();
+        let is_code = &self.code_map;
+        let mut statements = Vec::new();
+        for segment in self.segments() {
+            let mut address = segment.start as usize;
+            let end = address + segment.len;
+            while address < end {
+                let code_end = (address..end).find(|&at| !is_code[at]).unwrap_or(end);
+                let (kind, len) = match disassembly::decode(&self.data[address..code_end], &isa) {
+                    Some(decoded) if is_code[address] => decoded,
+                    _ => (DecodedKind::Data(self.data[address]), 1),
+                };
+                statements.push(Decoded {
+                    address: address as u16,
+                    len,
+                    kind,
+                });
+                address += len;
+            }
+        }
+        statements
+    }
+
+    /// Writes the byte code back as source, one statement per line, with a
+    /// `Lnnnn:` label at every jump target. Only the bytes of instructions are
+    /// disassembled, the rest being written as `.db`, so assembling the result
+    /// yields the same bytes.
+    pub fn to_asm(&self) -> String {
+        let statements = self.statements();
+        let target = |decoded: &Decoded| match decoded.kind {
+            DecodedKind::Instruction(
+                Mnemonic::Jmp | Mnemonic::Jmpc | Mnemonic::Jmpz | Mnemonic::Call,
+                [Some(Primitive::Memory(target)), _],
+            ) => Some(target),
+            _ => None,
+        };
+        let starts: HashSet<u16> = statements.iter().map(|decoded| decoded.address).collect();
+        let targets: HashSet<u16> = statements
+            .iter()
+            .filter_map(target)
+            .filter(|target| starts.contains(target))
+            .collect();
+        let mut asm = String::new();
+        let mut next = 0;
+        // The amount of bytes on the `.db` line being written
+        let mut data_line = 0;
+        for decoded in &statements {
+            if decoded.address != next {
+                asm.push_str(&format!(".org 0{:02x}h\n", decoded.address));
+                data_line = 0;
+            }
+            next = decoded.address + decoded.len as u16;
+            if targets.contains(&decoded.address) {
+                asm.push_str(&format!("L{:04x}:\n", decoded.address));
+                data_line = 0;
+            }
+            let bytes = match (&decoded.kind, target(decoded)) {
+                // Jumps into the middle of a statement have no label to use
+                (_, Some(target)) if !targets.contains(&target) => {
+                    let start = decoded.address as usize;
+                    &self.data[start..start + decoded.len]
+                }
+                (DecodedKind::Data(byte), _) => std::slice::from_ref(byte),
+                _ => {
+                    asm.push_str(&format!("\t{}\n", decoded));
+                    data_line = 0;
+                    continue;
+                }
+            };
+            for byte in bytes {
+                if data_line % 8 == 0 {
+                    asm.push_str(&format!("\t.db 0{:02x}h\n", byte));
+                } else {
+                    asm.pop();
+                    asm.push_str(&format!(", 0{:02x}h\n", byte));
+                }
+                data_line += 1;
+            }
+        }
+        match self.line_break(LineEnding::Lf) {
+            "\n" => asm,
+            line_break => asm.replace('\n', line_break),
+        }
+    }
+
     pub fn as_byte_code(&self) -> &[u8] {
         self.data.as_slice()
     }
@@ -62,6 +866,12 @@ impl Assembly {
     pub fn as_mut_byte_code(&mut self) -> &mut [u8] {
         self.data.as_mut_slice()
     }
+
+    /// Takes the byte code out of this `Assembly`, without writing it to the
+    /// save path.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
 }
 
 impl AsRef<[u8]> for Assembly {
@@ -76,35 +886,126 @@ impl AsMut<[u8]> for Assembly {
     }
 }
 
-impl Drop for Assembly {
-    #[allow(unused_must_use)]
-    fn drop(&mut self) {
-        if let Some(path) = self.path.take() {
-            fs::write(path, self);
+/// Yields the byte code. As with [`Assembly::into_bytes`], nothing is written
+/// to the save path.
+impl IntoIterator for Assembly {
+    type Item = u8;
+    type IntoIter = std::vec::IntoIter<u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_bytes().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Assembly {
+    type Item = &'a u8;
+    type IntoIter = std::slice::Iter<'a, u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+/// Appends raw byte code to the end of the assembly. The bytes are taken as
+/// they are: no labels are resolved, and keeping them consistent with the
+/// rest of the assembly is up to the caller.
+impl io::Write for Assembly {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        self.code_map.resize(self.data.len(), false);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The clone has no save path, so that only the original writes to it.
+impl Clone for Assembly {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            path: None,
+            source: self.source.clone(),
+            source_map: self.source_map.clone(),
+            labels: self.labels.clone(),
+            warnings: self.warnings.clone(),
+            gaps: self.gaps.clone(),
+            relocations: self.relocations.clone(),
+            hex_case: self.hex_case,
+            logisim_ending: self.logisim_ending,
+            line_ending: self.line_ending,
+            segments: self.segments.clone(),
+            code_map: self.code_map.clone(),
+            base_address: self.base_address,
+            transformed: self.transformed,
+            misuse: self.misuse,
         }
     }
 }
 
+/// Marks the bytes of `len` that fall within any of the `code` ranges.
+fn code_map(len: usize, code: &[Range<u16>]) -> Vec<bool> {
+    let mut is_code = vec![false; len];
+    for range in code {
+        is_code[range.start as usize..range.end as usize].fill(true);
+    }
+    is_code
+}
+
+/// Shows the byte code in hexadecimal. Its decimal form is available through
+/// the [`Debug`](fmt::Debug) of [`Assembly::as_byte_code`].
 impl fmt::Debug for Assembly {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.data.fmt(f)
+        struct Hex(u8, HexCase);
+
+        impl fmt::Debug for Hex {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let (hi, lo) = byte_as_hexadecimal(self.0, self.1);
+                write!(f, "{}{}", hi as char, lo as char)
+            }
+        }
+
+        f.debug_list()
+            .entries(self.data.iter().map(|&byte| Hex(byte, self.hex_case)))
+            .finish()
     }
 }
 
+/// Assemblies are equal when their byte code is. The save path, along with
+/// everything else learned from the source, is left out on purpose, so the
+/// same program compares equal wherever it is saved to.
 impl PartialEq for Assembly {
     fn eq(&self, other: &Self) -> bool {
         self.data == other.data
     }
 }
 
-fn byte_as_hexadecimal(byte: u8) -> (u8, u8) {
-    (nibble_to_ascii(byte >> 4), nibble_to_ascii(byte & 0xF))
+impl Eq for Assembly {}
+
+/// Hashes the byte code alone, consistently with [`PartialEq`].
+impl Hash for Assembly {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state);
+    }
 }
 
-fn nibble_to_ascii(nibble: u8) -> u8 {
+fn byte_as_hexadecimal(byte: u8, case: HexCase) -> (u8, u8) {
+    (
+        nibble_to_ascii(byte >> 4, case),
+        nibble_to_ascii(byte & 0xF, case),
+    )
+}
+
+fn nibble_to_ascii(nibble: u8, case: HexCase) -> u8 {
+    let a = match case {
+        HexCase::Lower => b'a',
+        HexCase::Upper => b'A',
+    };
     match nibble {
         0..=9 => b'0' + nibble,
-        0xA..=0xF => b'a' + nibble - 0xA,
+        0xA..=0xF => a + nibble - 0xA,
         _ => unreachable!("byte too large to represent a nibble"),
     }
 }
@@ -112,6 +1013,10 @@ fn nibble_to_ascii(nibble: u8) -> u8 {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        intel::token::TokenizingError, DataFlow, ErrorCode, Gaps, Instruction, Mnemonic, Port,
+        PortMask, Primitive, Register, Target, WarningCode,
+    };
 
     macro_rules! assert_asm {
         ($inst:literal, $translation:tt) => {
@@ -122,8 +1027,1574 @@ mod test {
         };
     }
 
+    macro_rules! assert_asm_err {
+        ($inst:literal, $code:pat) => {
+            match Assembly::assemble($inst.into()) {
+                Err(err) => assert!(matches!(err.code, $code), "{:?}", err),
+                Ok(assembly) => panic!("assembled into {:?}", assembly),
+            }
+        };
+    }
+
     #[test]
     fn test_instructions() {
         assert_asm!("_start:	jz	_start", [7, 5, 0, 0])
     }
+
+    #[test]
+    fn test_operandless() {
+        assert_asm!("pusha", [7, 7, 0b000_00_101]);
+        assert_asm!("popa", [7, 7, 0b000_00_110]);
+        assert_asm!("ret", [7, 7, 0b000_00_000]);
+        assert_asm_err!("pusha al", ErrorCode::ExcessiveOperands(0));
+        assert_asm_err!("mov al bl cl", ErrorCode::ExcessiveOperands(2));
+    }
+
+    #[test]
+    fn test_excessive_operand_span() {
+        for (src, span) in &[
+            ("mov al, bl, cl", 12..14),
+            ("push bl cl", 8..10),
+            ("ret, al", 5..7),
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert!(matches!(err.code, ErrorCode::ExcessiveOperands(_)));
+            assert_eq!(&err.token.span, span);
+        }
+    }
+
+    #[test]
+    fn test_unary() {
+        assert_asm!("inc al", [0b111_00_000]);
+        assert_asm!("not al", [0b101_00_000]);
+        assert_asm!("inc bl, al", [0b111_00_001]);
+        assert_asm!("inc el, al", [0b111_11_001]);
+        assert_asm!("not [10h], al", [0b101_00_010, 0, 0x10]);
+        // There is no flow that changes anything but `al` in place
+        for (src, stmt) in &[
+            ("inc bl", "inc bl, al"),
+            ("inc el", "inc el, al"),
+            ("not [10h]", "not [010h], al"),
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert_eq!(err.token.span, 4..src.len());
+            match err.code {
+                ErrorCode::UnaryOutsideAccumulator(suggested) => assert_eq!(suggested, *stmt),
+                code => panic!("unexpected error: {:?}", code),
+            }
+        }
+        assert_asm_err!("inc", ErrorCode::NotEnoughOperands(0, 1));
+        assert_asm_err!("not", ErrorCode::NotEnoughOperands(0, 1));
+        assert_asm_err!("add bl", ErrorCode::NotEnoughOperands(1, 2));
+    }
+
+    #[test]
+    fn test_nop() {
+        assert_asm!("nop", [0b110_00_000]);
+        assert_eq!(
+            Assembly::assemble("nop".into()).unwrap(),
+            Assembly::assemble("mov al, al".into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let joined = Assembly::assemble(".db 1h, 2h, 3h, 4h".into()).unwrap();
+        let split = Assembly::assemble(".db 1h, 2h, \\\n    3h, 4h".into()).unwrap();
+        assert_eq!(joined, split);
+        let split = Assembly::assemble("mov al,\\ \n5h".into()).unwrap();
+        assert_eq!(split, Assembly::assemble("mov al, 5h".into()).unwrap());
+        assert_asm!(".db 1h \\", [1]);
+        // Backslashes in comments continue no line
+        assert_asm!("ret ; see C:\\\nret", [7, 7, 0, 7, 7, 0]);
+        assert_asm!("mov al, '\\\\' ; \\\n.db 1h", [7, 0b110_00_000, b'\\', 1]);
+    }
+
+    #[test]
+    fn test_base_address() {
+        let options = AssembleOptions {
+            base_address: 0x200,
+            ..Default::default()
+        };
+        let src = "start: jmp end\nend: .jmptable start";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(assembly.as_byte_code(), [7, 3, 0x02, 0x04, 0x02, 0x00]);
+        assert_eq!(assembly.symbols(), [("start", 0x200), ("end", 0x204)]);
+        // `$` agrees with a label at the same spot
+        let src = ".db 1h\nhere: .dw $, here\n.assert $==205h";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(assembly.as_byte_code(), [1, 0x02, 0x01, 0x02, 0x01]);
+        let src = ".db 1h\n.assert $==201h\n.assert $<300h, \"too big\"";
+        assert!(Assembly::assemble_with(src.into(), &options).is_ok());
+
+        let options = AssembleOptions {
+            base_address: 0xffff,
+            ..Default::default()
+        };
+        let err = Assembly::assemble_with("nop\nend:".into(), &options).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::AddressOverflow(0xffff)));
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let assembly = Assembly::assemble(".db 1h, 2h, 3h".into()).unwrap();
+        let sum: u8 = (&assembly).into_iter().sum();
+        assert_eq!(sum, 6);
+        let bytes: Vec<u8> = assembly.into_iter().rev().collect();
+        assert_eq!(bytes, [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_split_rom_ram() {
+        let src = "jmp start\ntable: .db 1h, 2h\nstart: ret\n.org 0ch\n.db 3h";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        let (rom, ram) = assembly.split_rom_ram();
+        assert_eq!(rom, [7, 3, 0, 6, 0, 0, 7, 7, 0]);
+        assert_eq!(ram, [0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_is_code_byte() {
+        let src = "jmp start\n.db 7h, 7h\nstart: mov al, 7h\n.db 7h";
+        let mut assembly = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(assembly.as_byte_code(), [7, 3, 0, 6, 7, 7, 7, 0xc0, 7, 7]);
+        let is_code: Vec<_> = (0..10)
+            .map(|offset| assembly.is_code_byte(offset))
+            .collect();
+        let expected = [
+            true, true, true, true, false, false, true, true, true, false,
+        ];
+        assert_eq!(is_code, expected);
+        assert!(!assembly.is_code_byte(10));
+        io::Write::write_all(&mut assembly, &[7, 7, 0]).unwrap();
+        assert!(!assembly.is_code_byte(10));
+        assert_eq!(assembly.page_turn_count(), 2);
+    }
+
+    #[test]
+    fn test_assemble_into() {
+        let src = "start: mov al, 1h\n.org 8h\njmp start";
+        let mut buf = Vec::with_capacity(64);
+        buf.push(0xff);
+        Assembly::assemble_into(src, &mut buf).unwrap();
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(buf[0], 0xff);
+        assert_eq!(buf[1..], *assembly.as_byte_code());
+
+        let capacity = buf.capacity();
+        buf.clear();
+        Assembly::assemble_into(src, &mut buf).unwrap();
+        assert_eq!(buf, assembly.as_byte_code());
+        assert_eq!(buf.capacity(), capacity);
+
+        let err = Assembly::assemble_into("mov al, 1h\njmp nowhere", &mut buf).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::UnknownLabel(_)));
+        assert_eq!(buf, assembly.as_byte_code());
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut cache = HashSet::new();
+        assert!(cache.insert(Assembly::assemble("mov al, 1h".into()).unwrap()));
+        assert!(!cache.insert(Assembly::assemble("mov al, 01h".into()).unwrap()));
+        assert!(cache.insert(Assembly::assemble("mov al, 2h".into()).unwrap()));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_clone() {
+        let path = std::env::temp_dir().join("mpp_test_clone.bin");
+        let mut assembly = Assembly::assemble("start: jmp start".into()).unwrap();
+        assembly.then_save_as(&path);
+        let mut clone = assembly.clone();
+        assert_eq!(clone, assembly);
+        clone.to_logisim();
+        assert_ne!(clone.as_byte_code(), assembly.as_byte_code());
+        // Only the original has a save path to finish with
+        assert!(matches!(clone.finish(), Err(ErrorCode::NoSavePath)));
+        assembly.finish().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), assembly.as_byte_code());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_page_turn_count() {
+        let src = "mov al, 7h\nmov [bl], al\nret\n.db 7h, 7h, 0\nnop";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(assembly.page_turn_count(), 1 + 2 + 2);
+    }
+
+    #[test]
+    fn test_to_asm() {
+        let src =
+            "start: mov al, 'a'\nloop: inc al\nmov out0, al\ncall print\njc start\njmp loop\n\
+                   print: ret\ntable: .db 1h..9h\n.jmptable start, loop\n.org 40h\nmov al, [10h]";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        let asm = assembly.to_asm();
+        assert!(asm.starts_with("L0000:\n\tmov al, 061h\nL0003:\n\tinc al, al\n"));
+        assert!(asm.contains("\t.db 001h, 002h, 003h, 004h, 005h, 006h, 007h, 008h\n\t.db 009h, "));
+        assert!(asm.contains(".org 040h\n"));
+        assert_eq!(Assembly::assemble(asm).unwrap(), assembly);
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut progress = Vec::new();
+        let src = "mov al, 5h\n\n; comment\nmov [10h], al\nret";
+        Assembly::assemble_with_progress(src.into(), |line, len| progress.push((line, len)))
+            .unwrap();
+        assert_eq!(progress, [(1, 3), (2, 3), (3, 3), (4, 6), (5, 9)]);
+    }
+
+    #[test]
+    fn test_disassembly_roundtrip() {
+        let assembly = Assembly::assemble("label: je label\njc label".into()).unwrap();
+        let decoded = assembly.disassemble();
+        assert_eq!(decoded[0].to_string(), "jz L0000");
+        assert_eq!(decoded[1].to_string(), "jc L0000");
+        let src = decoded
+            .iter()
+            .map(|line| format!("L{:04x}: {}\n", line.address, line))
+            .collect::<String>();
+        assert_eq!(Assembly::assemble(src).unwrap(), assembly);
+
+        let src = "mov bl, 5h\nmov [10h], 3h\nadd al, [cl]\nmov [dl], al\nmov out1, al\n\
+                   xor al, in2\nsub al, [20h]\npusha\npopa\nret\n.db 0ffh";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        let src = assembly
+            .disassemble()
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        assert_eq!(Assembly::assemble(src).unwrap(), assembly);
+    }
+
+    #[test]
+    fn test_data() {
+        assert_asm!("table: .db 0xaa, 'b', 01b", [0xaa, b'b', 1]);
+        assert_asm!("jmp table\ntable: .db 0xaa", [7, 3, 0, 4, 0xaa]);
+        assert_asm_err!(".db 1h 2h", ErrorCode::MissingComma);
+        assert_asm_err!(".db 1h,", ErrorCode::UnexpectedComma);
+        assert_asm_err!(".db al", ErrorCode::BadData);
+        assert_asm!(".db 'A'..'D', ';'", [b'A', b'B', b'C', b'D', b';']);
+        assert_asm!(".db 30h..'2'", [b'0', b'1', b'2']);
+        assert_asm_err!(
+            ".db 'z'..'a'",
+            ErrorCode::Token(TokenizingError::DescendingRange)
+        );
+    }
+
+    #[test]
+    fn test_number_overflow() {
+        assert_asm!("mov al, 255", [7, 0b110_00_000, 0xff]);
+        for src in &[
+            "mov al, 0x1FF",
+            "mov al, 256",
+            "mov al, 100000h",
+            ".db 0..300",
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert!(matches!(
+                err.code,
+                ErrorCode::Token(TokenizingError::ByteOverflow)
+            ));
+        }
+        assert_asm_err!("mov al, 12z", ErrorCode::Token(TokenizingError::BadNumber));
+    }
+
+    #[test]
+    fn test_dw() {
+        assert_asm!(".dw 1234h, 5h, 0ffffh", [0x12, 0x34, 0, 5, 0xff, 0xff]);
+        assert_asm!("start: .dw start, end\nend:", [0, 0, 0, 4]);
+        assert_asm_err!(
+            ".dw 10000h",
+            ErrorCode::Token(TokenizingError::ByteOverflow)
+        );
+        assert_asm_err!(".dw bl", ErrorCode::BadData);
+        assert_asm_err!(".db 100h", ErrorCode::Token(TokenizingError::ByteOverflow));
+        let assembly = Assembly::assemble("start: .dw start".into()).unwrap();
+        assert_eq!(assembly.relocations(), [0]);
+    }
+
+    #[test]
+    fn test_decimal_literals() {
+        assert_asm!(
+            "mov al, 10\nmov al, 10d",
+            [7, 0b110_00_000, 10, 7, 0b110_00_000, 10]
+        );
+        assert_asm!(".db 0, 255, 1..3", [0, 255, 1, 2, 3]);
+        assert_asm!(
+            "mov al, [16]\nmov [32], al",
+            [0b110_00_101, 0, 16, 0b110_00_010, 0, 32]
+        );
+        assert_asm!(".dw 300, 65535", [0x01, 0x2c, 0xff, 0xff]);
+        assert_asm!(".db 2*3+1", [7]);
+        assert_asm!(".equ ten, 10\n.db ten", [10]);
+        let assembly = Assembly::assemble(".org 256\nret".into()).unwrap();
+        assert_eq!(assembly.as_byte_code().len(), 259);
+        // Suffixes still take precedence over the digits they follow
+        assert_asm!(".db 10b, 10h", [2, 16]);
+        assert_asm_err!(".db 256", ErrorCode::Token(TokenizingError::ByteOverflow));
+        assert_asm_err!(".db 1a", ErrorCode::Token(TokenizingError::BadNumber));
+    }
+
+    #[test]
+    fn test_dw_negative() {
+        assert_asm!(".dw -1h, -100h, 1h", [0xff, 0xff, 0xff, 0x00, 0, 1]);
+        assert_asm_err!(
+            ".dw -10000h",
+            ErrorCode::Token(TokenizingError::ByteOverflow)
+        );
+        // Bytes keep wrapping around as they did
+        assert_asm!("mov al, -1h\n.db -2h", [7, 0b110_00_000, 0xff, 0xfe]);
+        assert_asm_err!(".db -100h", ErrorCode::NumberOutOfRange(-256));
+        assert_asm!(".if -1h\n.db 1h\n.endif", [1]);
+    }
+
+    #[test]
+    fn test_dw_expression() {
+        assert_asm!(".dw 100h*2h+1h, 0ffh+1h", [0x02, 0x01, 0x01, 0x00]);
+        assert_asm!(
+            ".equ size, 80h\n.dw size*4h, 1h-2h",
+            [0x02, 0x00, 0xff, 0xff]
+        );
+        let assembly = Assembly::assemble(".org 100h\n.dw $+2h".into()).unwrap();
+        assert_eq!(assembly.as_byte_code()[0x100..], [0x01, 0x02]);
+        assert_asm_err!(".dw 100h*100h", ErrorCode::NumberOutOfRange(0x10000));
+        assert_asm_err!(".db 100h*2h", ErrorCode::NumberOutOfRange(0x200));
+    }
+
+    #[test]
+    fn test_dw_local_label() {
+        assert_asm!("1: .db 5h\n.dw 1b, 1f\n1: ret", [5, 0, 0, 0, 5, 7, 7, 0]);
+        let assembly = Assembly::assemble("1: .dw 1b".into()).unwrap();
+        assert_eq!(assembly.relocations(), [0]);
+        assert_asm_err!(".dw 1b", ErrorCode::UnknownLabel(_));
+    }
+
+    #[test]
+    fn test_patch_word() {
+        let src = "jmp start\nreserve: .dw 0\nstart: ret\nend:";
+        let options = AssembleOptions::new().base_address(0x100);
+        let mut assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assembly.patch_word("reserve", "start").unwrap();
+        assert_eq!(&assembly.as_byte_code()[4..6], &[0x01, 0x06]);
+        assert_eq!(assembly.relocations(), [2, 4]);
+        assert!(matches!(
+            assembly.patch_word("nowhere", "start"),
+            Err(ErrorCode::UnknownLabel(_))
+        ));
+        assert!(matches!(
+            assembly.patch_word("reserve", "nowhere"),
+            Err(ErrorCode::UnknownLabel(_))
+        ));
+        assert!(matches!(
+            assembly.patch_word("end", "start"),
+            Err(ErrorCode::AddressOutOfRange(9, 9))
+        ));
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let src = "1: dec: sub bl, 1h\njz 1f\njmp 1b\n1: .jmptable 1b, 1f\n1: ret";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        let named = "a: dec: sub bl, 1h\njz b\njmp a\nb: .jmptable b, c\nc: ret";
+        assert_eq!(assembly, Assembly::assemble(named.into()).unwrap());
+        assert_eq!(assembly.symbols(), [("dec", 0)]);
+        assert!(assembly.warnings().is_empty());
+        // Binary numbers are still read as such outside of jumps
+        assert_asm!(
+            "mov al, 101b\n.db 0b..11b",
+            [7, 0b110_00_000, 5, 0, 1, 2, 3]
+        );
+        assert_asm!(".if 1b\n.db 1h\n.endif", [1]);
+        assert_asm_err!("jmp 2b\n2: ret", ErrorCode::UnknownLabel(_));
+        assert_asm_err!("2: jmp 2f", ErrorCode::UnknownLabel(_));
+        assert_asm_err!("mov al, 1f", ErrorCode::Token(TokenizingError::BadNumber));
+    }
+
+    #[test]
+    fn test_zero_address() {
+        assert_asm!("mov al, [0]", [0b110_00_101, 0, 0]);
+        assert_asm!("mov [0h], al", [0b110_00_010, 0, 0]);
+        assert_asm!("mov al, 10", [7, 0b110_00_000, 10]);
+        assert_asm_err!("mov al, []", ErrorCode::Token(TokenizingError::BadMemory));
+    }
+
+    #[test]
+    fn test_listing() {
+        let src = "start:\n\tmov al, 41h ; load\n\tjmp start";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(
+            assembly.to_listing(src, Radix::Hexadecimal),
+            "                   start:\n\
+             0000  07 c0 41     \tmov al, 41h ; load\n\
+             0003  07 03 00 00  \tjmp start\n"
+        );
+        assert_eq!(
+            assembly.to_listing(src, Radix::Decimal).lines().nth(2),
+            Some("00003  007 003 000 000  \tjmp start")
+        );
+    }
+
+    #[test]
+    fn test_deterministic_output() {
+        let src = "c: b: a: .db 1h\nz: y: .db 2h, 3h\nx: jmp a";
+        let first = Assembly::assemble(src.into()).unwrap();
+        let second = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(
+            first.to_listing(src, Radix::Hexadecimal),
+            second.to_listing(src, Radix::Hexadecimal)
+        );
+        assert_eq!(
+            first.symbols(),
+            vec![("a", 0), ("b", 0), ("c", 0), ("y", 1), ("z", 1), ("x", 3)]
+        );
+        assert_eq!(first.symbols(), second.symbols());
+    }
+
+    #[test]
+    fn test_decoder_page() {
+        assert_eq!(Mnemonic::Mov.decoder_page(), 0);
+        assert_eq!(Mnemonic::Jmp.decoder_page(), 1);
+        assert_eq!(Mnemonic::Push.decoder_page(), 2);
+        let mov = Instruction::new().encode_mnemonic(Mnemonic::Mov);
+        let rom_to_acc = mov
+            .try_encode_data_flow(&Primitive::Number(1), &Primitive::Accumulator)
+            .unwrap();
+        assert_eq!(rom_to_acc.decoder_page(), 1);
+    }
+
+    #[test]
+    fn test_isa_table() {
+        let mut options = AssembleOptions::default();
+        options
+            .isa
+            .set_mnemonic(Mnemonic::Mov, (0b_001_11_111, 0b_001_00_000, 0))
+            .set_data_flow(DataFlow::RomToAcc, (0b_111_11_111, 0b_000_00_111, 0));
+        let assembly = Assembly::assemble_with("mov al, bl\nmov al, 1h".into(), &options);
+        assert_eq!(
+            assembly.unwrap().as_ref(),
+            [0b_001_00_100, 0b_001_00_111, 1]
+        );
+    }
+
+    #[test]
+    fn test_port_direction() {
+        assert_asm_err!("mov al, out2", ErrorCode::ReadFromOutput);
+        assert_asm_err!("mov in1, al", ErrorCode::WriteToInput);
+        assert_asm_err!("mov in1, out1", ErrorCode::ReadFromOutput);
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        let src = "mov out3, 0ffh";
+        assert_asm_err!("mov out3, 0ffh", ErrorCode::BadDestination);
+        let options = AssembleOptions {
+            pseudo_instructions: true,
+            ..AssembleOptions::default()
+        };
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        let expanded = Assembly::assemble("mov al, 0ffh\nmov out3, al".into()).unwrap();
+        assert_eq!(assembly, expanded);
+        assert!(matches!(
+            assembly.warnings(),
+            [AssemblyWarning {
+                code: WarningCode::ClobberedAccumulator,
+                ..
+            }]
+        ));
+
+        assert_asm_err!("mov bl, cl", ErrorCode::BadDestination);
+        let assembly = Assembly::assemble_with("mov bl, cl".into(), &options).unwrap();
+        let expanded = Assembly::assemble("mov al, cl\nmov bl, al".into()).unwrap();
+        assert_eq!(assembly, expanded);
+        assert_eq!(assembly.warnings().len(), 1);
+
+        let options = options.forbid_implicit_clobber(true);
+        for src in &["mov out3, 0ffh", "mov bl, cl"] {
+            let err = Assembly::assemble_with(src.to_string(), &options).unwrap_err();
+            assert!(matches!(err.code, ErrorCode::ImplicitClobber));
+            assert_eq!(err.token.span, 0..3);
+        }
+        let explicit = Assembly::assemble_with("mov al, cl\nmov bl, al".into(), &options).unwrap();
+        assert_eq!(explicit, expanded);
+    }
+
+    #[test]
+    fn test_available_ports() {
+        let options = AssembleOptions {
+            available_ports: Some(PortMask {
+                inputs: 0b0001,
+                outputs: 0b0011,
+            }),
+            ..AssembleOptions::default()
+        };
+        assert!(Assembly::assemble_with("mov out1, al\nmov al, in0".into(), &options).is_ok());
+        for src in &["mov out2, al", "mov al, in1"] {
+            let err = Assembly::assemble_with(src.to_string(), &options).unwrap_err();
+            assert!(matches!(err.code, ErrorCode::UnavailablePort(_)));
+        }
+    }
+
+    #[test]
+    fn test_typo_suggestion() {
+        for (src, name) in &[
+            ("mov al, b1", "bl"),
+            ("mov a1, 1h", "al"),
+            ("mov ouy0, al", "out0"),
+            ("mvo al, 1h", "mov"),
+            ("jaa end", "ja"),
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert_eq!(err.suggestion().as_deref(), Some(*name), "{}", src);
+            assert!(err.render(src, &"test.asm", None).contains(name));
+        }
+        for src in &["mov al, foo", "jmp lop"] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert_eq!(err.suggestion(), None);
+        }
+    }
+
+    #[test]
+    fn test_assemble_detailed() {
+        let src = "start: mov al, 1h\nmov out0, al\njmp start";
+        let options = AssembleOptions::default();
+        let (bytes, instructions, labels) = Assembly::assemble_detailed(src, &options).unwrap();
+        assert_eq!(bytes, Assembly::assemble(src.into()).unwrap().into_bytes());
+        assert_eq!(labels["start"], 0);
+        let mnemonics: Vec<_> = instructions.iter().map(|inst| inst.mnemonic()).collect();
+        assert_eq!(
+            mnemonics,
+            [
+                Some(Mnemonic::Mov),
+                Some(Mnemonic::Mov),
+                Some(Mnemonic::Jmp)
+            ]
+        );
+        assert_eq!(
+            instructions[2].operands(),
+            (Some(Primitive::Memory(0)), None)
+        );
+        let len: usize = instructions.iter().map(Instruction::byte_len).sum();
+        assert_eq!(len, bytes.len());
+    }
+
+    #[test]
+    fn test_run() {
+        // Adds 1 through 5 into `cl`, through a routine
+        let src = "\
+            mov al, 0h\n\
+            mov bl, al\n\
+            loop: call next\n\
+            mov al, bl\n\
+            sub al, 5h\n\
+            jz done\n\
+            jmp loop\n\
+            next: mov al, bl\n\
+            inc bl, al\n\
+            mov al, cl\n\
+            add al, bl\n\
+            mov cl, al\n\
+            ret\n\
+            done: mov al, cl\n\
+            mov out2, al\n\
+            pusha\n\
+            popa\n\
+            end: jmp end";
+        let state = Assembly::assemble(src.into()).unwrap().run(1000);
+        assert_eq!(state.halt, Some(crate::Halt::SelfJump));
+        assert_eq!(state.registers[1], 15);
+        assert_eq!(state.outputs[2], 15);
+        assert!(state.stack.is_empty() && state.call_stack.is_empty());
+        let state = Assembly::assemble("start: inc al\njmp start".into())
+            .unwrap()
+            .run(10);
+        assert_eq!((state.halt, state.steps, state.acc), (None, 10, 5));
+        let state = crate::run(&[7, 0b110_00_000, 0xff, 0b000_00_000], 10);
+        assert_eq!(state.halt, Some(crate::Halt::EndOfProgram));
+        assert_eq!((state.acc, state.carry, state.zero), (0xfe, true, false));
+    }
+
+    #[test]
+    fn test_primitive_conversions() {
+        use std::convert::TryFrom;
+        assert_eq!(Primitive::from(0x2a), Primitive::Number(0x2a));
+        assert_eq!(Primitive::try_from('a').unwrap(), Primitive::Number(b'a'));
+        assert_eq!(
+            Primitive::try_from('\u{7f}').unwrap(),
+            Primitive::Number(0x7f)
+        );
+        assert!(matches!(
+            Primitive::try_from('\u{80}'),
+            Err(TokenizingError::NonAscii)
+        ));
+        let inst = Instruction::new()
+            .encode_mnemonic(Mnemonic::Mov)
+            .try_encode_data_flow(&Primitive::try_from('A').unwrap(), &Primitive::Accumulator)
+            .unwrap();
+        assert_eq!(unsafe { inst.as_bytes() }, [7, 0b110_00_000, b'A']);
+    }
+
+    #[test]
+    fn test_target() {
+        let base = "base".parse::<Target>().unwrap().options();
+        assert!(Assembly::assemble_with("mov out0, al\npusha\nret".into(), &base).is_ok());
+        let err = Assembly::assemble_with("mov al, in3".into(), &base).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::UnavailablePort(_)));
+        let extended = Target::ExtendedIo.options();
+        assert!(Assembly::assemble_with("mov al, in3".into(), &extended).is_ok());
+        assert!("z80".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn test_shadowing_label() {
+        for src in &["add: ret", "al: ret", "bl: ret", "out1: ret", "jz: ret"] {
+            let assembly = Assembly::assemble(src.to_string()).unwrap();
+            assert!(matches!(
+                assembly.warnings(),
+                [AssemblyWarning {
+                    code: WarningCode::ShadowingLabel,
+                    ..
+                }]
+            ));
+        }
+        let assembly = Assembly::assemble("adder: ret".into()).unwrap();
+        assert!(assembly.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_fall_through_into_data() {
+        let assembly =
+            Assembly::assemble("sub al, 1h\njz end\ntable: .db 5h\nend:".into()).unwrap();
+        match assembly.warnings() {
+            [AssemblyWarning {
+                token,
+                code: WarningCode::FallThroughIntoData,
+            }] => assert_eq!(token.line, 2),
+            warnings => panic!("unexpected warnings: {:?}", warnings),
+        }
+        for src in &[
+            "jmp end\n.db 5h\nend:",
+            "ret\n.jmptable end\nend:",
+            "nop\n.org 10h\n.db 5h",
+            ".db 1h\n.db 2h",
+        ] {
+            let assembly = Assembly::assemble(src.to_string()).unwrap();
+            assert!(assembly.warnings().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_redundant_origin() {
+        let src = ".org 10h\nstart:\n.org 10h\nret";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        match assembly.warnings() {
+            [AssemblyWarning {
+                token,
+                code: WarningCode::RedundantOrigin(0x10),
+            }] => assert_eq!((token.line, token.span.clone()), (3, 0..4)),
+            warnings => panic!("unexpected warnings: {:?}", warnings),
+        }
+        for src in &[
+            ".org 10h\nret\n.org 20h",
+            ".org 0h\nret",
+            ".org 10h\n.org 20h",
+        ] {
+            let assembly = Assembly::assemble(src.to_string()).unwrap();
+            assert!(assembly.warnings().is_empty(), "{}", src);
+        }
+        let options = AssembleOptions::new().warnings_as_errors(true);
+        let err = Assembly::assemble_with(src.into(), &options).unwrap_err();
+        assert!(matches!(
+            err.code,
+            ErrorCode::Warning(WarningCode::RedundantOrigin(0x10))
+        ));
+    }
+
+    #[test]
+    fn test_extra_jump_operand() {
+        for (src, span) in &[
+            ("loop: jmp loop, al", 16..18),
+            ("loop: call loop, 1h", 17..19),
+            ("loop: jz loop bl", 14..16),
+            ("loop: jmp loop,", 14..15),
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert!(matches!(err.code, ErrorCode::ExtraJumpOperand), "{}", src);
+            assert_eq!(err.token.span, *span, "{}", src);
+        }
+        assert_asm_err!("push bl, cl", ErrorCode::ExcessiveOperands(1));
+    }
+
+    #[test]
+    fn test_verify_against() {
+        let src = "mov al, 1h\nret";
+        assert_eq!(
+            Assembly::verify_against(src, &[7, 0xc0, 1, 7, 7, 0]).unwrap(),
+            Ok(())
+        );
+        let mismatch = Assembly::verify_against(src, &[7, 0xc0, 2, 7, 7, 0])
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            mismatch,
+            Mismatch {
+                offset: 2,
+                expected: Some(2),
+                actual: Some(1)
+            }
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "expected 0x02 at offset 2, but found 0x01"
+        );
+        let mismatch = Assembly::verify_against(src, &[7, 0xc0, 1])
+            .unwrap()
+            .unwrap_err();
+        assert_eq!((mismatch.offset, mismatch.expected), (3, None));
+        let err = Assembly::verify_against("jmp nowhere", &[]).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::UnknownLabel(_)));
+    }
+
+    #[test]
+    fn test_dump_tokens() {
+        let tokens = Assembly::dump_tokens("start:mov al, @x ; bad\nret ; end");
+        let dump: Vec<_> = tokens
+            .iter()
+            .map(|token| format!("{}:{:?} {:?}", token.line, token.span, token.kind))
+            .collect();
+        assert_eq!(
+            dump,
+            [
+                "1:0..6 Label(\"start\")",
+                "1:6..9 Mnemonic(Mov)",
+                "1:10..12 Operand(Accumulator)",
+                "1:12..13 Comma",
+                "1:14..16 Error",
+                "2:0..3 Mnemonic(Ret)",
+                "2:4..9 Comment(\" end\")",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comparison_jumps() {
+        assert_asm_err!("end: ja end", ErrorCode::PseudoInstruction);
+        let options = AssembleOptions::new().pseudo_instructions(true);
+        let src = "sub al, 1h\nja end\njb end\nend: ret";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert!(assembly.warnings().is_empty());
+        assert_eq!(
+            assembly.as_byte_code(),
+            [
+                7,
+                0b001_00_000,
+                1,
+                7,
+                0b000_00_100,
+                0,
+                15,
+                7,
+                0b000_00_101,
+                0,
+                15,
+                7,
+                0b000_00_011,
+                0,
+                19,
+                7,
+                0b000_00_100,
+                0,
+                19,
+                7,
+                7,
+                0,
+            ]
+        );
+        assert_eq!(assembly.relocations(), [5, 9, 13, 17]);
+        // Writes to `out0` when below, `out1` when equal, and `out2` when above
+        for (value, port) in &[(2, 0), (3, 1), (4, 2)] {
+            let src = format!(
+                "mov al, {}h\nsub al, 3h\njb less\nja greater\n\
+                 mov out1, al\nret\nless: mov out0, al\nret\ngreater: mov out2, al\nret",
+                value
+            );
+            let state = Assembly::assemble_with(src, &options).unwrap().run(100);
+            let written: Vec<_> = (0..4).filter(|&num| state.outputs[num] != 0).collect();
+            let expected = if *port == 1 { vec![] } else { vec![*port] };
+            assert_eq!(written, expected, "{}", value);
+        }
+
+        let overflowing = options.clone().base_address(0xfff8);
+        let err = Assembly::assemble_with("start: ja start".into(), &overflowing).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::AddressOverflow(0xfff8)));
+
+        let src = "mov al, bl\nja end\nend: ret";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert!(matches!(
+            assembly.warnings(),
+            [AssemblyWarning {
+                code: WarningCode::UnsetFlag('C'),
+                ..
+            }]
+        ));
+        // Pseudo instructions take the encoding of the first instruction they
+        // expand into, and have none of their own to set
+        let jc = Instruction::new().encode_mnemonic(Mnemonic::Jmpc);
+        for &mnemonic in &Mnemonic::PSEUDO {
+            let inst = Instruction::new().encode_mnemonic(mnemonic);
+            assert_eq!(format!("{:?}", inst), format!("{:?}", jc));
+            assert_eq!(mnemonic.decoder_page(), 1);
+            let mut isa = IsaTable::default();
+            isa.set_mnemonic(mnemonic, (0, 0, 0));
+            assert_eq!(
+                isa.mnemonic(Mnemonic::Jmpc),
+                IsaTable::default().mnemonic(Mnemonic::Jmpc)
+            );
+        }
+    }
+
+    #[test]
+    fn test_unset_flag() {
+        let src = "mov al, bl\njz end\nsub al, 1h\nmov cl, al\njz end\njc end\nend: ret";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        let warnings: Vec<_> = assembly
+            .warnings()
+            .iter()
+            .map(|warning| (warning.token.line, &warning.code))
+            .collect();
+        assert!(matches!(warnings[..], [(2, WarningCode::UnsetFlag('Z'))]));
+        for src in &[
+            "add al, 1h\njc end\nend: ret",
+            "call routine\njz routine\nroutine: ret",
+            "loop: jz loop",
+        ] {
+            let assembly = Assembly::assemble(src.to_string()).unwrap();
+            assert!(assembly.warnings().is_empty(), "{}", src);
+        }
+        let assembly = Assembly::assemble("xor al, al\njc end\nend: ret".into()).unwrap();
+        assert_eq!(assembly.warnings().len(), 1);
+        assert_eq!(Mnemonic::Xor.affects_flags(), (false, true));
+        assert_eq!(Mnemonic::Mov.affects_flags(), (false, false));
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let src = "start: jmp start\nmov al, 1h\nmov bl, 2h\nret\nnext: ret";
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        match assembly.warnings() {
+            [AssemblyWarning {
+                token,
+                code: WarningCode::UnreachableCode,
+            }] => assert_eq!(token.line, 2),
+            warnings => panic!("unexpected warnings: {:?}", warnings),
+        }
+        for src in &["inc al\njz end\nret\nend: ret", "jmp end\n.db 1h\nend: ret"] {
+            let assembly = Assembly::assemble(src.to_string()).unwrap();
+            assert!(assembly.warnings().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_redefined_label() {
+        let src = "start: nop\nnop\n\tstart: ret";
+        let err = Assembly::assemble(src.into()).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::RedefinedLabel(1)));
+        let rendered = err.render(src, &"test.asm", Some("extra"));
+        assert!(rendered.ends_with("= note: previously defined at line 1\n  = note: extra\n"));
+    }
+
+    #[test]
+    fn test_collecting_errors() {
+        let options = AssembleOptions::default();
+        let src = "mov al, @foo\njmp nowhere\nret\nadd al\n.db 300";
+        let errors = Assembly::assemble_collecting_errors(src, &options, 20).unwrap_err();
+        let lines: Vec<usize> = errors.errors.iter().map(|err| err.token.line).collect();
+        assert_eq!(lines, [1, 2, 4, 5]);
+        assert_eq!(errors.overflow, 0);
+        let errors = Assembly::assemble_collecting_errors(src, &options, 2).unwrap_err();
+        assert_eq!(errors.errors.len(), 2);
+        assert_eq!(errors.overflow, 2);
+        let assembly = Assembly::assemble_collecting_errors("ret", &options, 20).unwrap();
+        assert_eq!(assembly, Assembly::assemble("ret".into()).unwrap());
+    }
+
+    #[test]
+    fn test_render_caret() {
+        for (src, caret) in &[
+            ("bad", "1 │ bad\n  │ ^^^ help"),
+            ("mov al, @foo", "1 │ mov al, @foo\n  │         ^^^^ help"),
+            (
+                "\t\tret, al",
+                "1 │         ret, al\n  │              ^^ help",
+            ),
+            ("mov al", "1 │ mov al\n  │       ^ help"),
+            // Tabs past the start of the line only go up to the next stop
+            (
+                "\tmov\tal,\t@x",
+                "1 │     mov al, @x\n  │             ^^ help",
+            ),
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            let rendered = err.render(src, &"test.asm", None);
+            assert!(rendered.contains(caret), "{}", rendered);
+        }
+        // The column reported is the one the caret is displayed at
+        let src = "\tmov\tal,\t@x";
+        let err = Assembly::assemble(src.into()).unwrap_err();
+        assert_eq!(err.token.span, 9..11);
+        assert!(err
+            .render(src, &"test.asm", None)
+            .contains("@ test.asm:1:d\n"));
+        for (radix, case, col_no) in [
+            (Radix::Binary, HexCase::Upper, "1101"),
+            (Radix::Decimal, HexCase::Upper, "13"),
+            (Radix::Hexadecimal, HexCase::Lower, "d"),
+            (Radix::Hexadecimal, HexCase::Upper, "D"),
+        ] {
+            let rendered = err.render_in(src, &"test.asm", None, radix, case);
+            assert!(rendered.contains(&format!("@ test.asm:1:{}\n", col_no)));
+        }
+    }
+
+    #[test]
+    fn test_inclusion_note() {
+        assert_eq!(crate::inclusion_note::<&str>(&[]), None);
+        let note = crate::inclusion_note(&["lib.asm", "main.asm"]).unwrap();
+        assert_eq!(note, "included from lib.asm, included from main.asm");
+        let src = "\tret\n\tbad";
+        let err = Assembly::assemble(src.into()).unwrap_err();
+        let rendered = err.render(src, &"util.asm", Some(&note));
+        assert!(rendered.contains("@ util.asm:2:"));
+        assert!(rendered.ends_with(&format!("= note: {}\n", note)));
+    }
+
+    #[test]
+    fn test_lexer_error_precedence() {
+        assert_asm_err!(
+            "jmp end\n9end:\nend: ret",
+            ErrorCode::Token(TokenizingError::BadLabel)
+        );
+        assert_asm_err!(
+            "jmp end\nmov al\n9end:",
+            ErrorCode::Token(TokenizingError::BadLabel)
+        );
+    }
+
+    #[test]
+    fn test_unknown_token() {
+        match Assembly::assemble("mov al, @Foo".into()) {
+            Err(err) => assert!(matches!(err.code, ErrorCode::UnknownToken(ref t) if t == "@foo")),
+            Ok(assembly) => panic!("assembled into {:?}", assembly),
+        }
+    }
+
+    #[test]
+    fn test_assemble_sync() {
+        let src = "start: mov al, 5h\njmp start";
+        let threaded = Assembly::assemble(src.into()).unwrap();
+        let sync = Assembly::assemble_sync(src).unwrap();
+        assert_eq!(threaded, sync);
+        assert!(matches!(
+            Assembly::assemble_sync("jmp end\nmov al\n9end:")
+                .unwrap_err()
+                .code,
+            ErrorCode::Token(TokenizingError::BadLabel)
+        ));
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let src = "pusha\nret";
+        let mut options = AssembleOptions {
+            check_stack_balance: true,
+            ..AssembleOptions::default()
+        };
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(assembly.warnings().len(), 1);
+        options.warnings_as_errors = true;
+        assert!(matches!(
+            Assembly::assemble_with(src.into(), &options)
+                .unwrap_err()
+                .code,
+            ErrorCode::Warning(WarningCode::StackImbalance(1))
+        ));
+
+        let src = "pusha\njc end\nend: ret";
+        let errors = Assembly::assemble_collecting_errors(src, &options, 10).unwrap_err();
+        let codes: Vec<_> = errors.errors.iter().map(|err| &err.code).collect();
+        assert!(matches!(
+            codes[..],
+            [
+                ErrorCode::Warning(WarningCode::UnsetFlag('C')),
+                ErrorCode::Warning(WarningCode::StackImbalance(1)),
+            ]
+        ));
+        let errors = Assembly::assemble_collecting_errors(src, &options, 1).unwrap_err();
+        assert_eq!((errors.errors.len(), errors.overflow), (1, 1));
+    }
+
+    #[test]
+    fn test_from_path() {
+        let path = std::env::temp_dir().join("mpp_test_from_path.asm");
+        fs::write(&path, "ret").unwrap();
+        let assembly = Assembly::from_path(&path).unwrap();
+        assert_eq!(assembly.source_path(), Some(path.as_path()));
+        assert_eq!(assembly.as_byte_code(), [7, 7, 0]);
+        fs::remove_file(&path).unwrap();
+        for &follow in &[FollowSymlinks::Yes, FollowSymlinks::No] {
+            let err = Assembly::from_path_opts(&path, follow).unwrap_err();
+            assert!(matches!(err.code, ErrorCode::Io(_)), "{:?}", follow);
+        }
+    }
+
+    #[test]
+    fn test_finish() {
+        let path = std::env::temp_dir().join("mpp_test_finish.hex");
+        fs::remove_file(&path).ok();
+        let mut assembly = Assembly::assemble(".db 1h, 0a2h".into()).unwrap();
+        assert!(matches!(assembly.finish(), Err(ErrorCode::NoSavePath)));
+        assembly.to_logisim().then_save_as(&path);
+        drop(assembly.clone());
+        assert!(!path.exists());
+        assembly.finish().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"v2.0 raw\r\n1 a2 ");
+        fs::remove_file(&path).unwrap();
+
+        let mut assembly = Assembly::assemble("ret".into()).unwrap();
+        assembly.to_logisim().to_logisim().then_save_as(&path);
+        assert!(matches!(
+            assembly.finish(),
+            Err(ErrorCode::RepeatedTransform)
+        ));
+        let mut assembly = Assembly::assemble("ret".into()).unwrap();
+        assembly.then_save_as(&path).to_logisim();
+        assert!(matches!(
+            assembly.finish(),
+            Err(ErrorCode::TransformAfterSave)
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_map_bytes() {
+        let src = "start: mov al, 1h\nadd al, 2h\njmp start";
+        let original = Assembly::assemble(src.into()).unwrap();
+        let mut assembly = original.clone();
+        assembly.map_bytes(|_, byte| byte);
+        assert_eq!(assembly, original);
+
+        let key = [0x5a, 0xc3, 0x0f];
+        let xor = |offset: usize, byte: u8| byte ^ key[offset % key.len()];
+        assembly.map_bytes(xor);
+        assert_ne!(assembly, original);
+        assert_eq!(assembly.as_byte_code()[0], 7 ^ 0x5a);
+        assembly.map_bytes(xor);
+        assert_eq!(assembly, original);
+
+        let mut assembly = original.clone();
+        assembly.map_bytes(xor).to_logisim();
+        assert!(matches!(assembly.finish(), Err(ErrorCode::NoSavePath)));
+        let mut assembly = original;
+        assembly.to_logisim().map_bytes(xor);
+        assert!(matches!(
+            assembly.finish(),
+            Err(ErrorCode::MapAfterTransform)
+        ));
+    }
+
+    #[test]
+    fn test_into_bytes() {
+        let path = std::env::temp_dir().join("mpp_test_into_bytes.bin");
+        fs::remove_file(&path).ok();
+        let mut assembly = Assembly::assemble(".db 1h, 2h".into()).unwrap();
+        assembly.then_save_as(&path);
+        assert_eq!(assembly.into_bytes(), vec![1, 2]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_max_decoder_page() {
+        let options = AssembleOptions {
+            max_decoder_page: Some(1),
+            ..Default::default()
+        };
+        assert!(Assembly::assemble_with("mov al, 5h".into(), &options).is_ok());
+        let err = Assembly::assemble_with("mov al, [bl]".into(), &options).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::UnsupportedInstruction(2, 1)));
+    }
+
+    #[test]
+    fn test_options_builder() {
+        let options = AssembleOptions::new()
+            .max_decoder_page(1)
+            .pseudo_instructions(true)
+            .define("debug", 1)
+            .base_address(0x100);
+        assert_eq!(options.max_decoder_page, Some(1));
+        assert!(options.pseudo_instructions);
+        let src = ".if debug\nstart: mov out0, 1h\n.endif\njmp start";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(assembly.symbols(), [("start", 0x100)]);
+        assert!(Assembly::assemble_with("ret".into(), &options).is_err());
+    }
+
+    #[test]
+    fn test_byte_code_builder() {
+        let options = AssembleOptions::default();
+        let mut builder = crate::ByteCodeBuilder::new(&options);
+        let jmp = Instruction::default().encode_mnemonic(Mnemonic::Jmp);
+        unsafe {
+            builder
+                .label("start")
+                .unwrap()
+                .emit_instruction(jmp)
+                .unwrap();
+        }
+        builder
+            .emit_address("table")
+            .unwrap()
+            .emit_bytes(&[1, 2])
+            .unwrap();
+        builder
+            .label("table")
+            .unwrap()
+            .emit_address("start")
+            .unwrap();
+        let assembly = builder.finish().unwrap();
+        let src = "start: jmp table\n.db 1h, 2h\ntable: .jmptable start";
+        assert_eq!(assembly, Assembly::assemble(src.into()).unwrap());
+
+        let mut builder = crate::ByteCodeBuilder::new(&options);
+        builder.label("start").unwrap();
+        assert!(builder.label("start").is_err());
+        let mut builder = crate::ByteCodeBuilder::new(&options);
+        builder.emit_address("nowhere").unwrap();
+        assert!(builder.finish().is_err());
+        let mut builder = crate::ByteCodeBuilder::new(&options);
+        assert!(matches!(
+            builder.emit_bytes(&[0; 0x10000]),
+            Err(AssemblyError {
+                code: ErrorCode::AddressOverflow(0),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_accumulator_aliases() {
+        let src = "mov acc, 1h\nadd bl, a\nmov out0, acc";
+        assert_asm_err!("mov acc, 1h", ErrorCode::BadDestination);
+        let options = AssembleOptions::new().accumulator_aliases(true);
+        let aliased = Assembly::assemble_with(src.into(), &options).unwrap();
+        let strict = Assembly::assemble("mov al, 1h\nadd bl, al\nmov out0, al".into()).unwrap();
+        assert_eq!(aliased, strict);
+        let src = ".equ a, 2h\nmov al, a\na: ret";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        let constant = Assembly::assemble("mov al, 2h".into()).unwrap();
+        assert!(assembly.as_byte_code().starts_with(constant.as_byte_code()));
+        assert!(matches!(
+            assembly.warnings()[0].code,
+            WarningCode::ShadowingLabel
+        ));
+        assert_asm_err!("mov ah, 1h", ErrorCode::Token(TokenizingError::HighByte));
+    }
+
+    #[test]
+    fn test_opcode_coverage() {
+        const OPERANDS: [&str; 7] = ["al", "bl", "[10h]", "in0", "out0", "5h", "[bl]"];
+        // Data flows are the same for every ALU operation
+        const FLOWS: [&str; 12] = [
+            "al, al",
+            "al, bl",
+            "al, [10h]",
+            "al, in0",
+            "al, 5h",
+            "al, [bl]",
+            "bl, al",
+            "bl, 5h",
+            "[10h], al",
+            "[10h], 5h",
+            "out0, al",
+            "[bl], al",
+        ];
+        const UNARY: [&str; 1] = ["al"];
+        let mut combos = vec![String::new(), "start".into()];
+        combos.extend(OPERANDS.iter().map(|operand| operand.to_string()));
+        for dest in &OPERANDS {
+            combos.extend(
+                OPERANDS
+                    .iter()
+                    .map(|origin| format!("{}, {}", dest, origin)),
+            );
+        }
+        for &mnemonic in Mnemonic::ALL.iter().chain(&Mnemonic::PSEUDO) {
+            let options =
+                AssembleOptions::new().pseudo_instructions(Mnemonic::PSEUDO.contains(&mnemonic));
+            let mut supported: Vec<&str> = combos
+                .iter()
+                .map(String::as_str)
+                .filter(|operands| {
+                    let src = format!("start: {} {}", mnemonic, operands);
+                    match Assembly::assemble_with(src, &options) {
+                        Ok(assembly) => !assembly.as_byte_code().is_empty(),
+                        Err(_) => false,
+                    }
+                })
+                .collect();
+            let mut expected: Vec<&str> = match mnemonic.operands_required() {
+                2 if mnemonic.is_unary() => UNARY.iter().chain(&FLOWS).copied().collect(),
+                2 => FLOWS.to_vec(),
+                1 => vec!["start"],
+                _ => vec![""],
+            };
+            expected.sort_unstable();
+            supported.sort_unstable();
+            assert_eq!(supported, expected, "{}", mnemonic);
+        }
+    }
+
+    #[test]
+    fn test_operand_before_mnemonic() {
+        let err = Assembly::assemble("al, bl mov".into()).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::OperandBeforeMnemonic));
+        assert_eq!(err.token.span, 0..2);
+        assert_asm_err!("start: 1h .db", ErrorCode::OperandBeforeMnemonic);
+        assert_asm_err!("al, bl", ErrorCode::NoMnemonic);
+    }
+
+    #[test]
+    fn test_decoded_operands() {
+        let isa = IsaTable::default();
+        let assembly =
+            Assembly::assemble("start: mov cl, 5h\nmov [10h], al\nadd al, in2\njmp start".into())
+                .unwrap();
+        let mut bytes = assembly.as_byte_code();
+        let mut decoded = Vec::new();
+        while let Some((inst, len)) = Instruction::decode(bytes, &isa) {
+            decoded.push((inst.mnemonic(), inst.operands()));
+            bytes = &bytes[len..];
+        }
+        assert_eq!(
+            decoded,
+            [
+                (
+                    Some(Mnemonic::Mov),
+                    (
+                        Some(Primitive::Register(Register::C)),
+                        Some(Primitive::Number(5))
+                    )
+                ),
+                (
+                    Some(Mnemonic::Mov),
+                    (Some(Primitive::Memory(0x10)), Some(Primitive::Accumulator))
+                ),
+                (
+                    Some(Mnemonic::Add),
+                    (
+                        Some(Primitive::Accumulator),
+                        Some(Primitive::Port(Port::Input(2)))
+                    )
+                ),
+                (Some(Mnemonic::Jmp), (Some(Primitive::Memory(0)), None)),
+            ]
+        );
+        assert!(bytes.is_empty());
+        // Jump targets are emitted apart from the instruction
+        let jmp = Instruction::new().encode_mnemonic(Mnemonic::Jmp);
+        assert_eq!(jmp.operands(), (None, None));
+    }
+
+    #[test]
+    fn test_accumulator_address() {
+        assert_asm_err!("mov [al], bl", ErrorCode::AccumulatorAddress);
+        assert_asm_err!("mov [al], 10h", ErrorCode::AccumulatorAddress);
+        assert_asm_err!("add al, [al]", ErrorCode::AccumulatorAddress);
+        assert_asm!("mov [bl], al", [0x07, 0x07, 0b110_00_010]);
+    }
+
+    #[test]
+    fn test_strip_trailing() {
+        let src = ".db 1h, 0h, 0h, 2h\n.org 8h\n.db 0h";
+        let mut assembly = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(assembly.strip_trailing(0).as_byte_code(), &[1, 0, 0, 2]);
+        assert_eq!(assembly.segments(), [Segment { start: 0, len: 4 }]);
+        // The operands of the last instruction are kept, zeroed or not
+        let mut assembly = Assembly::assemble("mov al, 0h\n.db 0h, 0h".into()).unwrap();
+        let mov = Assembly::assemble("mov al, 0h".into()).unwrap();
+        assert_eq!(
+            assembly.strip_trailing(0).as_byte_code(),
+            mov.as_byte_code()
+        );
+        let mut assembly = Assembly::assemble(".db 0ffh, 0ffh".into()).unwrap();
+        assert!(assembly.strip_trailing(0xff).as_byte_code().is_empty());
+        assert!(assembly.segments().is_empty());
+    }
+
+    #[test]
+    fn test_default_output_path() {
+        let path = |input: &str, format| default_output_path(Path::new(input), format);
+        assert_eq!(
+            path("src/prog.asm", OutputFormat::Binary),
+            Path::new("src/prog.bin")
+        );
+        assert_eq!(
+            path("prog.asm", OutputFormat::Logisim),
+            Path::new("prog.hex")
+        );
+        assert_eq!(path("prog", OutputFormat::Listing), Path::new("prog.lst"));
+        assert_eq!(
+            path("prog.bin", OutputFormat::Binary),
+            Path::new("prog.bin.bin")
+        );
+    }
+
+    #[test]
+    fn test_org() {
+        assert_asm!(".db 1h\n.org 4h\nend: .db 2h", [1, 0, 0, 0, 2]);
+        assert_asm_err!(".db 1h, 2h\n.org 1h", ErrorCode::BackwardOrigin(1, 2));
+        assert_asm_err!(".org 1h, 2h", ErrorCode::ExcessiveOperands(1));
+        assert_asm_err!(".org 0ffffh\n.db 1h, 2h", ErrorCode::AddressOverflow(0));
+        assert_asm_err!(".org 0fffeh\njmp far\nfar:", ErrorCode::AddressOverflow(0));
+        let assembly = Assembly::assemble("jmp far\n.org 100h\nfar: ret".into()).unwrap();
+        assert_eq!(assembly.as_byte_code().len(), 0x103);
+        assert_eq!(assembly.as_byte_code()[..4], [7, 3, 1, 0]);
+        assert_eq!(assembly.symbols(), [("far", 0x100)]);
+        let asm = assembly.to_asm();
+        assert!(asm.contains(".org 0100h\n"));
+        assert_eq!(Assembly::assemble(asm).unwrap(), assembly);
+        let src = ".db 1h\n.org 4h\n.db 2h";
+        let options = AssembleOptions {
+            gaps: Gaps::Fill(0xff),
+            ..AssembleOptions::default()
+        };
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(assembly.as_byte_code(), &[1, 0xff, 0xff, 0xff, 2]);
+        assert!(assembly.gaps().is_empty());
+        let options = AssembleOptions {
+            gaps: Gaps::Sparse,
+            ..AssembleOptions::default()
+        };
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(
+            assembly.segments(),
+            vec![Segment { start: 0, len: 1 }, Segment { start: 4, len: 1 }]
+        );
+        assert_eq!(assembly.gaps().len(), 1);
+        assert_eq!(assembly.gaps()[0], 1..4);
+        assert_eq!(
+            assembly.to_listing(src, Radix::Hexadecimal),
+            "0000  01  .db 1h\n          .org 4h\n0004  02  .db 2h\n"
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_asm!(
+            ".equ base, 10h\n.equ offset, 2h\nmov al, base+offset*3h-1h",
+            [0x07, 0xc0, 0x15]
+        );
+        assert_asm!(".db -1h+'a', 'a'+1h, '+'", [0x60, 0x62, 0x2b]);
+        assert_asm_err!(".db 0ffh*2h", ErrorCode::NumberOutOfRange(510));
+        assert_asm_err!(".db nowhere+1h", ErrorCode::UnknownConstant(_));
+        assert_asm_err!(".equ a, 1h\n.equ a, 2h", ErrorCode::RedefinedLabel(1));
+        assert_asm_err!(".equ a", ErrorCode::NotEnoughOperands(1, 2));
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_asm!(
+            ".db 1h, 2h\n.assert $==2h, \"too big\"\n.assert $<100h",
+            [1, 2]
+        );
+        let src = ".org 0ffh\n.db 1h\n.assert $<100h, \"Code Too Big\"";
+        match Assembly::assemble(src.into()).unwrap_err().code {
+            ErrorCode::AssertionFailed(Some(msg)) => assert_eq!(msg, "Code Too Big"),
+            code => panic!("unexpected error: {:?}", code),
+        }
+        assert_asm_err!(".assert 0h", ErrorCode::AssertionFailed(None));
+        assert_asm_err!(
+            ".assert \"no condition\"",
+            ErrorCode::NotEnoughOperands(1, 2)
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let src = ".if DEBUG\n.db 1h\n.if 0h\n.db 2h\n.else\n.db 3h\n.endif\n.else\n.db 4h\n.endif";
+        let mut options = AssembleOptions::default();
+        let release = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(release.as_byte_code(), &[4]);
+        options.defines.insert("DEBUG".into(), 1);
+        let debug = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(debug.as_byte_code(), &[1, 3]);
+        assert_asm_err!(".if 1h\n.db 1h", ErrorCode::UnterminatedConditional);
+        assert_asm_err!(".endif", ErrorCode::UnmatchedConditional);
+        assert_asm_err!(
+            ".if 1h\n.else\n.else\n.endif",
+            ErrorCode::UnmatchedConditional
+        );
+        for src in &[
+            "foo: .if 1h\n.endif",
+            ".if 0h\nfoo: .endif",
+            ".if 1h\na: b: .else\n.endif",
+        ] {
+            let err = Assembly::assemble(src.to_string()).unwrap_err();
+            assert!(matches!(err.code, ErrorCode::LabelOnConditional), "{}", src);
+            assert!(matches!(err.token.kind, TokenKind::Label(_)));
+        }
+    }
+
+    #[test]
+    fn test_jump_table() {
+        assert_asm!(
+            "a: .db 1h\nb: .db 2h\ntable: .jmptable a, b, table",
+            [0x01, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02]
+        );
+        assert_asm_err!(".jmptable nowhere", ErrorCode::UnknownLabel(_));
+        assert_asm_err!(".jmptable 1h", ErrorCode::NoLabel);
+    }
+
+    #[test]
+    fn test_write() {
+        use std::io::Write;
+        let mut assembly = Assembly::assemble(".db 1h".into()).unwrap();
+        assembly.write_all(&[2, 3]).unwrap();
+        assert_eq!(assembly.as_byte_code(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_relocations() {
+        let assembly = Assembly::assemble("a: jmp a\n.db 1h\n.jmptable a, a".into()).unwrap();
+        assert_eq!(assembly.relocations(), &[2, 5, 7]);
+        let mut assembly = Assembly::assemble(".db 0h, 0h\nstart: .db 1h".into()).unwrap();
+        assembly.with_reset_vector(0, "start").unwrap();
+        assert_eq!(assembly.relocations(), &[0]);
+    }
+
+    #[test]
+    fn test_debug() {
+        let assembly = Assembly::assemble("mov al, 41h".into()).unwrap();
+        assert_eq!(format!("{:?}", assembly), "[07, c0, 41]");
+        assert_eq!(format!("{:?}", assembly.as_byte_code()), "[7, 192, 65]");
+    }
+
+    #[test]
+    fn test_hex_case() {
+        let options = AssembleOptions {
+            hex_case: HexCase::Upper,
+            ..AssembleOptions::default()
+        };
+        let src = ".db 0abh";
+        let assembly = Assembly::assemble_with(src.into(), &options).unwrap();
+        assert_eq!(format!("{:?}", assembly), "[AB]");
+        assert_eq!(
+            assembly.to_listing(src, Radix::Hexadecimal),
+            "0000  AB  .db 0abh\n"
+        );
+        let assembly = Assembly::assemble(src.into()).unwrap();
+        assert_eq!(format!("{:?}", assembly), "[ab]");
+    }
+
+    #[test]
+    fn test_logisim_ending() {
+        let mut assembly = Assembly::assemble("mov al, 1h".into()).unwrap();
+        assembly.to_logisim();
+        assert!(assembly.as_byte_code().ends_with(b"c0 1 "));
+        let options = AssembleOptions::new().logisim_ending(LogisimEnding::Newline);
+        let mut assembly = Assembly::assemble_with("mov al, 1h".into(), &options).unwrap();
+        assembly.to_logisim();
+        assert!(assembly.as_byte_code().ends_with(b"c0 1\r\n"));
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let src = "mov al, 1h\n.org 8h\nret";
+        let logisim = |options: &AssembleOptions, addressed: bool| {
+            let mut assembly = Assembly::assemble_with(src.into(), options).unwrap();
+            if addressed {
+                assembly.to_logisim_addressed();
+            } else {
+                assembly.to_logisim();
+            }
+            String::from_utf8(assembly.into_bytes()).unwrap()
+        };
+        let default = AssembleOptions::new().logisim_ending(LogisimEnding::Newline);
+        let lf = default.clone().line_ending(LineEnding::Lf);
+        let crlf = AssembleOptions::new().line_ending(LineEnding::CrLf);
+        for &addressed in &[false, true] {
+            let (native, unix) = (logisim(&default, addressed), logisim(&lf, addressed));
+            assert_eq!(native.replace("\r\n", "\n"), unix);
+            assert_eq!(native.len(), unix.len() + native.matches("\r\n").count());
+            assert!(!unix.contains('\r'));
+        }
+        assert_eq!(logisim(&lf, false), "v2.0 raw\n7 c0 1 0 0 0 0 0 7 7 0\n");
+
+        let assembly = Assembly::assemble_with(src.into(), &crlf).unwrap();
+        let plain = Assembly::assemble(src.into()).unwrap();
+        let outputs = [
+            (assembly.hexdump(), plain.hexdump()),
+            (assembly.to_asm(), plain.to_asm()),
+            (
+                assembly.to_listing(src, Radix::Hexadecimal),
+                plain.to_listing(src, Radix::Hexadecimal),
+            ),
+        ];
+        for (windows, unix) in &outputs {
+            assert!(!unix.contains('\r'));
+            assert_eq!(windows.len(), unix.len() + unix.lines().count());
+            assert_eq!(&windows.replace("\r\n", "\n"), unix);
+        }
+    }
+
+    #[test]
+    fn test_logisim_addressed() {
+        let src = "mov al, 1h\n.org 20h\n.db 0..16\n.org 40h\nret";
+        let mut assembly = Assembly::assemble(src.into()).unwrap();
+        assembly.to_logisim_addressed();
+        assert_eq!(
+            String::from_utf8(assembly.as_byte_code().to_vec()).unwrap(),
+            "v3.0 hex words addressed\r\n\
+             0000: 07 c0 01\r\n\
+             0020: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\r\n\
+             0030: 10\r\n\
+             0040: 07 07 00\r\n"
+        );
+        let options = AssembleOptions::new().base_address(0x100);
+        let mut assembly = Assembly::assemble_with("nop".into(), &options).unwrap();
+        assembly.to_logisim_addressed();
+        assert!(assembly.as_byte_code().ends_with(b"0100: c0\r\n"));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let assembly = Assembly::assemble("mov al, 41h\nmov al, 0ah".into()).unwrap();
+        assert_eq!(
+            assembly.hexdump(),
+            "00000000: 07c0 4107 c00a                           ..A...\n"
+        );
+    }
+
+    #[test]
+    fn test_reset_vector() {
+        let mut assembly = Assembly::assemble(".db 0h, 0h\nstart: ret".into()).unwrap();
+        assembly.with_reset_vector(0, "start").unwrap();
+        assert_eq!(&assembly.as_byte_code()[..2], [0, 2]);
+        assert!(matches!(
+            assembly.with_reset_vector(0, "end"),
+            Err(ErrorCode::UnknownLabel(_))
+        ));
+        assert!(matches!(
+            assembly.with_reset_vector(4, "start"),
+            Err(ErrorCode::AddressOutOfRange(4, 5))
+        ));
+    }
+
+    #[test]
+    fn test_swapped_operands() {
+        assert_asm!("mov al, 5h", [7, 0b110_00_000, 5]);
+        assert_asm_err!("mov 5h, al", ErrorCode::SwappedOperands);
+        assert_asm_err!("mov bl, cl", ErrorCode::BadDestination);
+    }
 }
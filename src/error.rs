@@ -1,4 +1,7 @@
-use crate::intel::token::{Token, TokenizingError};
+use crate::{
+    intel::token::{Mnemonic, Port, Primitive, Token, TokenKind, TokenizingError},
+    HexCase, Radix,
+};
 use std::{fmt, path::Path};
 use thiserror::Error;
 
@@ -6,31 +9,88 @@ const TAB_SIZE: usize = 4;
 
 #[derive(Debug, Error)]
 pub enum ErrorCode {
+    #[error("Accumulator used as an address")]
+    AccumulatorAddress,
+    #[error("Address out of range")]
+    AddressOutOfRange(usize, usize),
+    #[error("Address overflow")]
+    AddressOverflow(u16),
+    #[error("Assertion failed")]
+    AssertionFailed(Option<String>),
+    #[error("Invalid data")]
+    BadData,
+    #[error("Origin behind the current address")]
+    BackwardOrigin(usize, usize),
     #[error("Invalid data origin")]
     BadOrigin,
     #[error("Invalid data destination")]
     BadDestination,
     #[error("Too many operands found")]
     ExcessiveOperands(usize),
+    #[error("Too many operands for a jump")]
+    ExtraJumpOperand,
+    #[error("Accumulator clobbered implicitly")]
+    ImplicitClobber,
+    #[error("Label on a conditional directive")]
+    LabelOnConditional,
+    #[error("Byte code mapped after it was transformed")]
+    MapAfterTransform,
+    #[error("Missing comma between operands")]
+    MissingComma,
     #[error("Multiple mnemonics in a single statement")]
     MultipleMnemonics,
     #[error("Destination label not found")]
     NoLabel,
     #[error("No mnemonic found")]
     NoMnemonic,
+    #[error("No path to save to")]
+    NoSavePath,
     #[error("Too few operands provided")]
     NotEnoughOperands(usize, usize),
+    #[error("Operand before the mnemonic")]
+    OperandBeforeMnemonic,
+    #[error("Pseudo instructions not enabled")]
+    PseudoInstruction,
+    #[error("Cannot read from an output port")]
+    ReadFromOutput,
+    #[error("Number out of range")]
+    NumberOutOfRange(i64),
+    /// The line where the label was first defined
     #[error("Redefined label")]
-    RedefinedLabel,
+    RedefinedLabel(usize),
+    #[error("Byte code transformed twice")]
+    RepeatedTransform,
+    #[error("Operands in the wrong order")]
+    SwappedOperands,
+    #[error("Byte code transformed after its save path was set")]
+    TransformAfterSave,
+    #[error("Conditional block never ended")]
+    UnterminatedConditional,
     #[error("Unexpected comma")]
     UnexpectedComma,
     #[error("Unexpected label")]
     UnexpectedLabel,
+    #[error("Single operand other than the accumulator")]
+    UnaryOutsideAccumulator(String),
+    #[error("Port not available on the target")]
+    UnavailablePort(Port),
+    #[error("No conditional block to continue")]
+    UnmatchedConditional,
     #[error("Undefined label")]
     UnknownLabel(String),
+    #[error("Undefined constant")]
+    UnknownConstant(String),
+    #[error("Unknown token")]
+    UnknownToken(String),
+    #[error("Instruction not supported by the target")]
+    UnsupportedInstruction(usize, usize),
+    #[error("Cannot write to an input port")]
+    WriteToInput,
     #[error(transparent)]
     Token(#[from] TokenizingError),
     #[error(transparent)]
+    Warning(#[from] WarningCode),
+    #[error(transparent)]
     Io(#[from] std::io::Error),
 }
 
@@ -47,29 +107,110 @@ impl ErrorCode {
                     "and `Input -> Acc`".into(),
                 ];
             }
+            AddressOutOfRange(at, len) => format!(
+                "a word at {} does not fit in the {} bytes assembled",
+                at, len
+            ),
+            AddressOverflow(0) => "this lands past 0xffff, the last address there is".into(),
+            AddressOverflow(base) => format!(
+                "this address lands past 0xffff once the base address {:#06x} is added",
+                base
+            ),
+            AssertionFailed(Some(msg)) => msg.clone(),
+            AssertionFailed(None) => "this condition does not hold".into(),
+            BackwardOrigin(origin, address) => format!(
+                "`.org` may only move forward, but the address is already at {}, past {}",
+                address, origin
+            ),
+            BadData => "only number literals and characters may be emitted as data".into(),
+            AccumulatorAddress => {
+                "memory may only be addressed through a register, as in `mov [bl], al`".into()
+            }
+            ExcessiveOperands(0) => "no operands are required".into(),
+            ExtraJumpOperand => {
+                "jumps only take the label they go to; conditional ones test the flags, as `jz` does".into()
+            }
+            ImplicitClobber => {
+                "this statement expands into instructions that overwrite `al`; move the value through `al` explicitly".into()
+            }
             ExcessiveOperands(req) => format!(
                 "only {} operand{} required",
                 req,
                 if *req > 1 { "s are" } else { " is" }
             ),
+            LabelOnConditional => "move the label to a line of its own, inside or outside the block".into(),
+            MapAfterTransform => "map the bytes before turning them into another format".into(),
+            MissingComma => "add a comma before this operand".into(),
             MultipleMnemonics => "remove this mnemonic".into(),
             NoLabel => "add a label operand".into(),
             NoMnemonic => "add a mnemonic".into(),
+            NoSavePath => "set one with `then_save_as` beforehand".into(),
             NotEnoughOperands(found, req) => {
                 let amt = req - found;
                 format!("add {} operand{}", amt, if amt > 1 { "s" } else { "" })
             }
-            RedefinedLabel => "remove this label or rename it".into(),
+            OperandBeforeMnemonic => "operands go after the mnemonic, as in `mov al, bl`".into(),
+            PseudoInstruction => {
+                "this expands into several instructions; enable `pseudo_instructions` to allow it".into()
+            }
+            ReadFromOutput => "output ports may only be written to, as in `mov out0, al`".into(),
+            NumberOutOfRange(value) => {
+                let size = if (-0xffff..=0xffff).contains(value) { "a byte" } else { "a word" };
+                format!("this amounts to {}, which does not fit in {}", value, size)
+            }
+            RedefinedLabel(_) => "remove this label or rename it".into(),
+            RepeatedTransform => "the byte code may only be turned into another format once".into(),
+            TransformAfterSave => "set the save path once the byte code is in its final format".into(),
+            SwappedOperands => {
+                return vec![
+                    "did you mean to swap them? Intel syntax expects".into(),
+                    "`mnemonic destination, origin`".into(),
+                ];
+            }
+            UnterminatedConditional => "add an `.endif` after the end of the block".into(),
             UnexpectedComma => "remove this comma".into(),
             UnexpectedLabel => "this mnemonic does not accept labels".into(),
+            UnaryOutsideAccumulator(stmt) => format!(
+                "only `al` is changed in place; write `{}` to store the result of `al` here",
+                stmt
+            ),
+            UnavailablePort(port) => {
+                let (kind, num) = match port {
+                    Port::Input(num) => ("in", num),
+                    Port::Output(num) => ("out", num),
+                };
+                format!("`{}{}` is not wired up on this target", kind, num)
+            }
+            UnmatchedConditional => "add a `.if` before this, or remove it".into(),
             UnknownLabel(label) => format!(
                 "add this label somewhere either before a mnemonic, or alone, as `{}:`",
                 label
             ),
+            UnknownConstant(name) => {
+                format!("define this constant beforehand, as in `.equ {}, 0h`", name)
+            }
+            UnknownToken(token) => format!(
+                "`{}` is not a valid mnemonic, register, port, or label",
+                token
+            ),
+            UnsupportedInstruction(page, max) => format!(
+                "this instruction lives in decoder page {}, but the target only goes up to page {}",
+                page, max
+            ),
+            WriteToInput => "input ports may only be read from, as in `mov al, in0`".into(),
             Token(err) => err.help_msg().into(),
+            Warning(warning) => return warning.help_msg(),
             Io(io) => io.to_string(),
         }]
     }
+
+    /// Where else in the source the error stems from, if anywhere.
+    fn note(&self) -> Option<String> {
+        match self {
+            ErrorCode::RedefinedLabel(line) => Some(format!("previously defined at line {}", line)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -90,66 +231,342 @@ impl AssemblyError {
         Self { token, code }
     }
 
+    /// Prints the rendered error to the standard error output.
     pub fn throw<P>(&self, src: &str, src_path: &P, note: Option<&str>)
     where
         P: AsRef<Path>,
     {
-        let mut err_col = self.token.span.start;
-        let line_src = src
-            .lines()
-            .nth(self.token.line - 1)
-            .expect("error line could not be found");
-        let mut line = String::with_capacity(line_src.len());
-        // Replace tabs with spaces
-        for ch in line_src.chars() {
-            if ch == '\t' {
-                let amt = TAB_SIZE - ((line.len() + 1) % TAB_SIZE);
-                line.extend(std::iter::repeat('\x20').take(amt));
-                if err_col == self.token.span.start {
-                    err_col += amt;
+        eprint!("{}", self.render(src, src_path, note));
+    }
+
+    /// The register, port, or mnemonic the offending word is a single typo
+    /// away from, as `bl` is for `b1` and `mov` for `mvo`.
+    pub fn suggestion(&self) -> Option<String> {
+        use ErrorCode::*;
+        let word = match (&self.code, &self.token.kind) {
+            (UnknownToken(word), _) => word,
+            (
+                BadOrigin | BadDestination | NoMnemonic,
+                TokenKind::Operand(Primitive::Label(word)),
+            ) => word,
+            _ => return None,
+        };
+        let registers = ["al", "bl", "cl", "dl", "el"];
+        let ports = (0..4).flat_map(|num| vec![format!("in{}", num), format!("out{}", num)]);
+        let operands = registers.iter().map(|&name| name.into()).chain(ports);
+        let mnemonics = Mnemonic::ALL
+            .iter()
+            .chain(&Mnemonic::PSEUDO)
+            .map(Mnemonic::to_string);
+        let candidates: Vec<String> = match self.code {
+            BadOrigin | BadDestination => operands.collect(),
+            NoMnemonic => mnemonics.collect(),
+            _ => operands.chain(mnemonics).collect(),
+        };
+        let word = word.to_lowercase();
+        candidates
+            .into_iter()
+            .find(|name| edit_distance(&word, name) <= 1)
+    }
+
+    /// Renders the error along with the offending line of `src` and the help
+    /// messages, as printed by [`throw`](Self::throw). The error's own note,
+    /// if any, comes before `note`. The column number is written in the
+    /// default [`Radix`] and [`HexCase`], as listings are.
+    pub fn render<P>(&self, src: &str, src_path: &P, note: Option<&str>) -> String
+    where
+        P: AsRef<Path>,
+    {
+        self.render_in(src, src_path, note, Radix::default(), HexCase::default())
+    }
+
+    /// Renders the error as [`render`](Self::render) does, with the column
+    /// number written in `radix`, and in `case` if hexadecimal.
+    pub fn render_in<P>(
+        &self,
+        src: &str,
+        src_path: &P,
+        note: Option<&str>,
+        radix: Radix,
+        case: HexCase,
+    ) -> String
+    where
+        P: AsRef<Path>,
+    {
+        let own_note = self.code.note();
+        let notes: Vec<&str> = own_note.as_deref().into_iter().chain(note).collect();
+        let mut help_msg = self.code.help_msg();
+        if let Some(name) = self.suggestion() {
+            help_msg.push(format!("did you mean `{}`?", name));
+        }
+        render(
+            &self.code.to_string(),
+            help_msg,
+            &self.token,
+            src,
+            src_path.as_ref(),
+            &notes,
+            (radix, case),
+        )
+    }
+}
+
+/// Every error found in a source, as reported by
+/// [`Assembly::assemble_collecting_errors`](crate::Assembly::assemble_collecting_errors).
+#[derive(Debug)]
+pub struct AssemblyErrors {
+    /// The errors in the order of their lines, up to the requested amount
+    pub errors: Vec<AssemblyError>,
+    /// How many more errors were found beyond those
+    pub overflow: usize,
+}
+
+impl From<AssemblyError> for AssemblyErrors {
+    fn from(err: AssemblyError) -> Self {
+        Self {
+            errors: vec![err],
+            overflow: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum WarningCode {
+    #[error("Accumulator clobbered")]
+    ClobberedAccumulator,
+    #[error("Execution runs into data")]
+    FallThroughIntoData,
+    #[error("Redundant origin")]
+    RedundantOrigin(u16),
+    #[error("Label named after a reserved word")]
+    ShadowingLabel,
+    #[error("Stack may overflow")]
+    StackOverflow(usize),
+    #[error("Unbalanced stack")]
+    StackImbalance(isize),
+    #[error("Unreachable code")]
+    UnreachableCode,
+    #[error("Flag tested before being set")]
+    UnsetFlag(char),
+}
+
+impl WarningCode {
+    fn help_msg(&self) -> Vec<String> {
+        use WarningCode::*;
+        vec![match self {
+            ClobberedAccumulator => {
+                "this statement expands into instructions that overwrite `al`".into()
+            }
+            FallThroughIntoData => {
+                "the data after this instruction is run as code; add a `jmp` or `ret`".into()
+            }
+            RedundantOrigin(origin) => format!(
+                "the previous `.org` already moved to {:#06x}, and nothing was emitted since",
+                origin
+            ),
+            ShadowingLabel => {
+                "mnemonics, registers, and ports are best left out of label names".into()
+            }
+            StackOverflow(size) => format!(
+                "this push goes beyond the {} byte{} of stack available",
+                size,
+                if *size == 1 { "" } else { "s" }
+            ),
+            StackImbalance(delta) => {
+                let amt = delta.abs();
+                let plural = if amt > 1 { "s" } else { "" };
+                if *delta > 0 {
+                    format!(
+                        "{} value{} pushed in this routine are never popped",
+                        amt, plural
+                    )
+                } else {
+                    format!(
+                        "{} value{} popped in this routine were never pushed",
+                        amt, plural
+                    )
                 }
-            } else {
-                line.push(ch);
             }
+            UnreachableCode => {
+                "the instruction before never runs on to this one, and no label leads here".into()
+            }
+            UnsetFlag(flag) => format!(
+                "no instruction since the last label sets the `{}` flag this jump tests",
+                flag
+            ),
+        }]
+    }
+}
+
+/// A likely mistake which does not stop the source from being assembled.
+#[derive(Clone, Debug, Error)]
+pub struct AssemblyWarning {
+    pub token: Token,
+    #[source]
+    pub code: WarningCode,
+}
+
+impl fmt::Display for AssemblyWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code)
+    }
+}
+
+impl AssemblyWarning {
+    pub fn new(token: Token, code: WarningCode) -> Self {
+        Self { token, code }
+    }
+
+    /// Prints the rendered warning to the standard error output.
+    pub fn throw<P>(&self, src: &str, src_path: &P, note: Option<&str>)
+    where
+        P: AsRef<Path>,
+    {
+        eprint!("{}", self.render(src, src_path, note));
+    }
+
+    /// Renders the warning in the same fashion as
+    /// [`AssemblyError::render`](AssemblyError::render).
+    pub fn render<P>(&self, src: &str, src_path: &P, note: Option<&str>) -> String
+    where
+        P: AsRef<Path>,
+    {
+        render(
+            &format!("Warning: {}", self.code),
+            self.code.help_msg(),
+            &self.token,
+            src,
+            src_path.as_ref(),
+            &note.into_iter().collect::<Vec<_>>(),
+            (Radix::default(), HexCase::default()),
+        )
+    }
+}
+
+/// Describes the chain of files that led to the one being assembled,
+/// innermost first, to be passed as the `note` of
+/// [`AssemblyError::throw`](AssemblyError::throw) and friends. There is no
+/// note when nothing was included.
+pub fn inclusion_note<P>(includers: &[P]) -> Option<String>
+where
+    P: AsRef<Path>,
+{
+    if includers.is_empty() {
+        return None;
+    }
+    let chain: Vec<String> = includers
+        .iter()
+        .map(|path| format!("included from {}", path.as_ref().to_string_lossy()))
+        .collect();
+    Some(chain.join(", "))
+}
+
+/// How many characters have to be inserted, removed, replaced, or swapped with
+/// the next one to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // `dist[i][j]` is the distance between the first `i` characters of `a`
+    // and the first `j` of `b`
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            dist[i][j] = match (i, j) {
+                (0, _) => j,
+                (_, 0) => i,
+                _ => {
+                    let replace = dist[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+                    let mut best = replace.min(dist[i - 1][j] + 1).min(dist[i][j - 1] + 1);
+                    if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                        best = best.min(dist[i - 2][j - 2] + 1);
+                    }
+                    best
+                }
+            };
         }
-        let ruler_width = (self.token.line as f64).log10() as usize + 1;
-        let help_msg = self.code.help_msg();
-        eprintln!(
-            "\
-        {err_msg} @ {file_name}:{line_no}:{col_no}\n\
-		{line_no:width$} │ {line}\n\
-		{spacing:width$} │ {spacing:col_pad$}{indicator:^<indicator_width$} help: {help_msg}\
-		",
-            err_msg = self.code,
-            file_name = src_path.as_ref().to_string_lossy(),
-            line_no = self.token.line,
-            col_no = err_col,
-            line = line,
-            spacing = "",
-            width = ruler_width,
-            col_pad = err_col - 1,
-            indicator = "",
-            indicator_width = self.token.span.len(),
-            help_msg = help_msg[0]
-        );
-        for msg in help_msg.iter().skip(1) {
-            eprintln!(
-                "{spacing:width$} │ {spacing:col_pad$}{pad:pad_width$}{help_msg}",
-                spacing = "",
-                width = ruler_width,
-                col_pad = err_col - 1,
-                pad = "",
-                pad_width = self.token.span.len() + 7,
-                help_msg = msg
-            );
+    }
+    dist[a.len()][b.len()]
+}
+
+fn render(
+    msg: &str,
+    help_msg: Vec<String>,
+    token: &Token,
+    src: &str,
+    src_path: &Path,
+    notes: &[&str],
+    (radix, case): (Radix, HexCase),
+) -> String {
+    use fmt::Write;
+    let line_src = src
+        .lines()
+        .nth(token.line - 1)
+        .expect("error line could not be found");
+    let mut line = String::with_capacity(line_src.len());
+    // Where the token starts once tabs are expanded, counted from 0
+    let mut err_col = None;
+    let mut width = 0;
+    // Replace tabs with spaces, up to the next tab stop
+    for (col, ch) in line_src.chars().enumerate() {
+        if col == token.span.start {
+            err_col = Some(width);
         }
-        if let Some(note) = note {
-            eprintln!(
-                "{:width$} = note: {note}",
-                "",
-                width = ruler_width,
-                note = note
-            );
+        if ch == '\t' {
+            let amt = TAB_SIZE - width % TAB_SIZE;
+            line.extend(std::iter::repeat('\x20').take(amt));
+            width += amt;
+        } else {
+            line.push(ch);
+            width += 1;
         }
     }
+    // Spans may start past the end of the line, as for missing operands
+    let err_col = err_col.unwrap_or(width + token.span.start - line_src.chars().count());
+    let ruler_width = (token.line as f64).log10() as usize + 1;
+    // Writing to a `String` never fails
+    let mut rendered = String::new();
+    writeln!(
+        rendered,
+        "\
+    {err_msg} @ {file_name}:{line_no}:{col_no}\n\
+	{line_no:width$} │ {line}\n\
+	{spacing:width$} │ {spacing:col_pad$}{indicator:^<indicator_width$} help: {help_msg}\
+	",
+        err_msg = msg,
+        file_name = src_path.to_string_lossy(),
+        line_no = token.line,
+        col_no = radix.format_number(err_col + 1, case),
+        line = line,
+        spacing = "",
+        width = ruler_width,
+        col_pad = err_col,
+        indicator = "",
+        indicator_width = token.span.len(),
+        help_msg = help_msg[0]
+    )
+    .unwrap();
+    for msg in help_msg.iter().skip(1) {
+        writeln!(
+            rendered,
+            "{spacing:width$} │ {spacing:col_pad$}{pad:pad_width$}{help_msg}",
+            spacing = "",
+            width = ruler_width,
+            col_pad = err_col,
+            pad = "",
+            pad_width = token.span.len() + 7,
+            help_msg = msg
+        )
+        .unwrap();
+    }
+    for note in notes {
+        writeln!(
+            rendered,
+            "{:width$} = note: {note}",
+            "",
+            width = ruler_width,
+            note = note
+        )
+        .unwrap();
+    }
+    rendered
 }
@@ -0,0 +1,155 @@
+//! Reads byte code back into [`Mnemonic`]s and their operands, undoing the
+//! encoding described in the [`instruction`](super::instruction) module.
+//!
+//! Every opcode is written with a single spelling, so that feeding the
+//! disassembly back to the assembler yields the very same bytes. Jump targets
+//! are written as labels named after their address, as in `L002a`.
+
+use super::{instruction::*, token::*};
+use std::fmt;
+
+const ALU_MASK: u8 = 0b_111_00_000;
+const MIDDLE_MASK: u8 = 0b_000_11_000;
+const FLOW_MASK: u8 = 0b_000_00_111;
+
+/// A statement read back from byte code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decoded {
+    /// Where the statement starts
+    pub address: u16,
+    /// How many bytes the statement takes, page turns included
+    pub len: usize,
+    pub kind: DecodedKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodedKind {
+    /// An instruction with its destination and origin operands, in that
+    /// order. Jump targets are given as a [`Primitive::Memory`].
+    Instruction(Mnemonic, [Option<Primitive>; 2]),
+    /// A byte that does not start any instruction
+    Data(u8),
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Mnemonic::*;
+        match &self.kind {
+            DecodedKind::Instruction(
+                mnemonic @ (Jmp | Jmpc | Jmpz | Call),
+                [Some(Primitive::Memory(addr)), _],
+            ) => {
+                write!(f, "{} L{:04x}", mnemonic, addr)
+            }
+            DecodedKind::Instruction(mnemonic, [None, _]) => write!(f, "{}", mnemonic),
+            DecodedKind::Instruction(mnemonic, [Some(dest), None]) => {
+                write!(f, "{} {}", mnemonic, dest)
+            }
+            DecodedKind::Instruction(mnemonic, [Some(dest), Some(origin)]) => {
+                write!(f, "{} {}, {}", mnemonic, dest, origin)
+            }
+            DecodedKind::Data(byte) => write!(f, ".db 0{:02x}h", byte),
+        }
+    }
+}
+
+/// Decodes every statement in `bytes` according to `isa`. Bytes that do not
+/// form an instruction are kept as [`DecodedKind::Data`], one at a time.
+pub fn disassemble(bytes: &[u8], isa: &IsaTable) -> Vec<Decoded> {
+    let mut decoded = Vec::new();
+    let mut address = 0;
+    while address < bytes.len() {
+        let (kind, len) =
+            decode(&bytes[address..], isa).unwrap_or((DecodedKind::Data(bytes[address]), 1));
+        decoded.push(Decoded {
+            address: address as u16,
+            len,
+            kind,
+        });
+        address += len;
+    }
+    decoded
+}
+
+/// Decodes the instruction at the start of `bytes`, along with the amount of
+/// bytes it takes.
+pub(crate) fn decode(bytes: &[u8], isa: &IsaTable) -> Option<(DecodedKind, usize)> {
+    use Mnemonic::*;
+    let page = bytes
+        .iter()
+        .take_while(|&&byte| byte == Instruction::DECODER_PAGE_TURN)
+        .count();
+    let main = *bytes.get(page)?;
+    let mut len = page + 1;
+    let ram = |len: &mut usize| {
+        let word = bytes.get(*len..*len + 2)?;
+        *len += 2;
+        Some(Primitive::Memory(u16::from_be_bytes([word[0], word[1]])))
+    };
+    let middle = (main & MIDDLE_MASK) >> 3;
+    let register = [Register::B, Register::C, Register::D, Register::E][middle as usize];
+
+    // Instructions encoded by their mnemonic alone. `nop` is left out in favor
+    // of the `mov al, al` it stands for.
+    for &mnemonic in &Mnemonic::ALL {
+        let (_, or, mnemonic_page) = isa.mnemonic(mnemonic);
+        let takes_register = matches!(mnemonic, Push | Pop);
+        let main = if takes_register {
+            main & !MIDDLE_MASK
+        } else {
+            main
+        };
+        if mnemonic.operands_required() == 2
+            || mnemonic == Nop
+            || mnemonic_page != page
+            || main != or
+        {
+            continue;
+        }
+        let operand = match mnemonic {
+            Jmp | Jmpc | Jmpz | Call => Some(ram(&mut len)?),
+            Push | Pop => Some(Primitive::Register(register)),
+            _ => None,
+        };
+        return Some((DecodedKind::Instruction(mnemonic, [operand, None]), len));
+    }
+
+    // ALU operations with a data flow
+    let mnemonic = Mnemonic::ALL.iter().copied().find(|&mnemonic| {
+        mnemonic.operands_required() == 2 && isa.mnemonic(mnemonic).1 & ALU_MASK == main & ALU_MASK
+    })?;
+    let flow = DataFlow::ALL.iter().copied().find(|&flow| {
+        let (_, or, flow_page) = isa.data_flow(flow);
+        flow_page == page && or & FLOW_MASK == main & FLOW_MASK
+    })?;
+    let rom = |len: &mut usize| {
+        let byte = *bytes.get(*len)?;
+        *len += 1;
+        Some(Primitive::Number(byte))
+    };
+    use DataFlow::*;
+    use Primitive::{Accumulator, DynamicMemory, Port as IoPort, Register as Reg};
+    let operands = match flow {
+        AccToAcc => [Accumulator, Accumulator],
+        AccToRegister => [Reg(register), Accumulator],
+        AccToRam => [ram(&mut len)?, Accumulator],
+        AccToOutput => [IoPort(Port::Output(middle)), Accumulator],
+        RegisterToAcc => [Accumulator, Reg(register)],
+        RamToAcc => [Accumulator, ram(&mut len)?],
+        InputToAcc => [Accumulator, IoPort(Port::Input(middle))],
+        RomToAcc => [Accumulator, rom(&mut len)?],
+        RomToRegister => [Reg(register), rom(&mut len)?],
+        RomToRam => {
+            // The literal comes before the memory location
+            let origin = rom(&mut len)?;
+            [ram(&mut len)?, origin]
+        }
+        DynamicRamToAcc => [Accumulator, DynamicMemory(register)],
+        AccToDynamicRam => [DynamicMemory(register), Accumulator],
+    };
+    let [dest, origin] = operands;
+    Some((
+        DecodedKind::Instruction(mnemonic, [Some(dest), Some(origin)]),
+        len,
+    ))
+}
@@ -0,0 +1,121 @@
+//! Constant expressions: numbers and constants joined by `+`, `-`, and `*`,
+//! as in `base+2h*offset`, which fold into a single value once every constant
+//! is known. Multiplications take precedence, and a leading sign applies to
+//! the first term only.
+//!
+//! Two such sums may be compared with `<`, `<=`, `>`, `>=`, `==`, or `!=`,
+//! which folds into `1` if the comparison holds, or `0` otherwise. The current
+//! address may be used as a term through `$`, base address included, just as
+//! a label placed there would have it.
+
+use super::token::{try_to_word, Primitive};
+use crate::ErrorCode;
+
+const COMPARISONS: [&str; 6] = ["<=", ">=", "==", "!=", "<", ">"];
+
+/// Whether `src` is anything more than a single literal or name.
+pub(crate) fn is_expression(src: &str) -> bool {
+    !operators(src).is_empty()
+        || comparison(src).is_some()
+        || unquoted(src).any(|(_, ch)| ch == b'$')
+}
+
+/// Folds `src` into a single value, taking the value of every constant, and
+/// of `$`, from `lookup`.
+pub(crate) fn evaluate<F>(src: &str, lookup: F) -> Result<i64, ErrorCode>
+where
+    F: Fn(&str) -> Option<i64>,
+{
+    let (idx, op) = match comparison(src) {
+        Some(comparison) => comparison,
+        None => return sum(src, &lookup),
+    };
+    let lhs = sum(&src[..idx], &lookup)?;
+    let rhs = sum(&src[idx + op.len()..], &lookup)?;
+    let holds = match op {
+        "<=" => lhs <= rhs,
+        ">=" => lhs >= rhs,
+        "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        _ => lhs > rhs,
+    };
+    Ok(holds as i64)
+}
+
+fn sum<F>(src: &str, lookup: &F) -> Result<i64, ErrorCode>
+where
+    F: Fn(&str) -> Option<i64>,
+{
+    let (mut sign, mut term_start) = match src.as_bytes().first() {
+        Some(b'-') => (-1, 1),
+        Some(b'+') => (1, 1),
+        _ => (1, 0),
+    };
+    let mut sum = 0i64;
+    let mut product = 1i64;
+    let ops = operators(src);
+    for (idx, op) in ops.into_iter().chain(std::iter::once((src.len(), b'+'))) {
+        let value = term_value(&src[term_start..idx], lookup)?;
+        product = product.saturating_mul(value);
+        if op != b'*' {
+            sum = sum.saturating_add(sign * product);
+            product = 1;
+            sign = if op == b'-' { -1 } else { 1 };
+        }
+        term_start = idx + 1;
+    }
+    Ok(sum)
+}
+
+fn term_value<F>(term: &str, lookup: &F) -> Result<i64, ErrorCode>
+where
+    F: Fn(&str) -> Option<i64>,
+{
+    // Terms may go beyond a byte, as to be compared against addresses
+    if let Some(word) = try_to_word(term) {
+        return Ok(word as i64);
+    } else if term == "$" {
+        return lookup(term).ok_or_else(|| ErrorCode::UnknownConstant(term.into()));
+    }
+    match term.parse() {
+        Ok(Primitive::Number(value)) => Ok(value as i64),
+        Ok(Primitive::Label(name)) => lookup(&name).ok_or(ErrorCode::UnknownConstant(name)),
+        Ok(_) => Err(ErrorCode::BadData),
+        Err(err) => Err(ErrorCode::Token(err)),
+    }
+}
+
+/// The positions of every arithmetic operator in `src`, leaving out leading
+/// signs.
+fn operators(src: &str) -> Vec<(usize, u8)> {
+    unquoted(src)
+        .filter(|&(idx, ch)| idx > 0 && matches!(ch, b'+' | b'-' | b'*'))
+        .collect()
+}
+
+/// The position of the first comparison in `src`, and the comparison itself.
+fn comparison(src: &str) -> Option<(usize, &'static str)> {
+    unquoted(src).find_map(|(idx, _)| {
+        COMPARISONS
+            .iter()
+            .find(|op| src[idx..].starts_with(*op))
+            .map(|op| (idx, *op))
+    })
+}
+
+/// Every byte of `src` outside of quotes, and its position.
+fn unquoted(src: &str) -> impl Iterator<Item = (usize, u8)> + '_ {
+    let mut quote = None;
+    src.bytes().enumerate().filter(move |&(_, ch)| match ch {
+        b'\'' | b'"' if quote == Some(ch) => {
+            quote = None;
+            false
+        }
+        b'\'' | b'"' if quote.is_none() => {
+            quote = Some(ch);
+            false
+        }
+        _ => quote.is_none(),
+    })
+}
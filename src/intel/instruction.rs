@@ -83,12 +83,153 @@
 //! | Pusha           |   101    |      2       |
 //! | Popa            |   110    |      2       |
 
-use super::token::*;
+use super::{
+    disassembly::{self, DecodedKind},
+    token::*,
+};
 use crate::ErrorCode;
 
+/// The encoding of a [`Mnemonic`] or a [`DataFlow`]: the masks that are
+/// respectively and-ed and or-ed to the main instruction byte, and the decoder
+/// page it lives in.
+pub type Encoding = (u8, u8, usize);
+
+/// The ways data may flow in an instruction. See the [module's](self)
+/// documentation for their encodings.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DataFlow {
+    AccToAcc,
+    AccToRegister,
+    AccToRam,
+    AccToOutput,
+    RegisterToAcc,
+    RamToAcc,
+    InputToAcc,
+    RomToAcc,
+    RomToRegister,
+    RomToRam,
+    DynamicRamToAcc,
+    AccToDynamicRam,
+}
+
+impl DataFlow {
+    pub(crate) const ALL: [Self; 12] = [
+        Self::AccToAcc,
+        Self::AccToRegister,
+        Self::AccToRam,
+        Self::AccToOutput,
+        Self::RegisterToAcc,
+        Self::RamToAcc,
+        Self::InputToAcc,
+        Self::RomToAcc,
+        Self::RomToRegister,
+        Self::RomToRam,
+        Self::DynamicRamToAcc,
+        Self::AccToDynamicRam,
+    ];
+}
+
+/// The encodings used for every [`Mnemonic`] and [`DataFlow`]. The default
+/// table is the one documented in the [module's](self) documentation, but any
+/// of them may be overridden to target a modified instruction set.
+#[derive(Clone, Debug)]
+pub struct IsaTable {
+    mnemonics: Vec<Encoding>,
+    data_flows: Vec<Encoding>,
+}
+
+impl Default for IsaTable {
+    fn default() -> Self {
+        let mnemonic = |mnemonic| {
+            use Mnemonic::*;
+            match mnemonic {
+                // ALU
+                Add => (0b_000_11_111, 0b_000_00_000, 0),
+                Sub => (0b_001_11_111, 0b_001_00_000, 0),
+                And => (0b_010_11_111, 0b_010_00_000, 0),
+                Or => (0b_011_11_111, 0b_011_00_000, 0),
+                Xor => (0b_100_11_111, 0b_100_00_000, 0),
+                Not => (0b_101_11_111, 0b_101_00_000, 0),
+                Mov => (0b_110_11_111, 0b_110_00_000, 0),
+                Inc => (0b_111_11_111, 0b_111_00_000, 0),
+                // Flow control
+                Jmp => (0b_111_11_011, 0b_000_00_011, 1),
+                Jmpc => (0b_111_11_100, 0b_000_00_100, 1),
+                Jmpz => (0b_111_11_101, 0b_000_00_101, 1),
+                Call => (0b_111_11_110, 0b_000_00_110, 1),
+                Ret => (0b_111_11_000, 0b_000_00_000, 2),
+                Push => (0b_111_11_011, 0b_000_00_011, 2),
+                Pop => (0b_111_11_100, 0b_000_00_100, 2),
+                Pusha => (0b_111_11_101, 0b_000_00_101, 2),
+                Popa => (0b_111_11_110, 0b_000_00_110, 2),
+                // Same as `mov al, al`
+                Nop => (0b_110_11_000, 0b_110_00_000, 0),
+                Ja | Jb => unreachable!("pseudo instructions are not in `Mnemonic::ALL`"),
+            }
+        };
+        let data_flow = |flow| {
+            use DataFlow::*;
+            match flow {
+                AccToAcc => (0b_111_11_000, 0b_000_00_000, 0),
+                AccToRegister => (0b_111_11_001, 0b_000_00_001, 0),
+                AccToRam => (0b_111_11_010, 0b_000_00_010, 0),
+                AccToOutput => (0b_111_11_011, 0b_000_00_011, 0),
+                RegisterToAcc => (0b_111_11_100, 0b_000_00_100, 0),
+                RamToAcc => (0b_111_11_101, 0b_000_00_101, 0),
+                InputToAcc => (0b_111_11_110, 0b_000_00_110, 0),
+                RomToAcc => (0b_111_11_000, 0b_000_00_000, 1),
+                RomToRegister => (0b_111_11_001, 0b_000_00_001, 1),
+                RomToRam => (0b_111_11_010, 0b_000_00_010, 1),
+                DynamicRamToAcc => (0b_111_11_001, 0b_000_00_001, 2),
+                AccToDynamicRam => (0b_111_11_010, 0b_000_00_010, 2),
+            }
+        };
+        Self {
+            mnemonics: Mnemonic::ALL.iter().copied().map(mnemonic).collect(),
+            data_flows: DataFlow::ALL.iter().copied().map(data_flow).collect(),
+        }
+    }
+}
+
+impl IsaTable {
+    /// The encoding of `mnemonic`. Pseudo instructions take the one of the
+    /// first instruction they expand into, as `ja` and `jb` do with `jc`.
+    pub fn mnemonic(&self, mnemonic: Mnemonic) -> Encoding {
+        self.mnemonics[mnemonic.encoded_as() as usize]
+    }
+
+    pub fn data_flow(&self, flow: DataFlow) -> Encoding {
+        self.data_flows[flow as usize]
+    }
+
+    /// Sets the encoding of `mnemonic`. Pseudo instructions have none of
+    /// their own, so they are left as they are: set the encodings of the
+    /// instructions they expand into instead.
+    pub fn set_mnemonic(&mut self, mnemonic: Mnemonic, encoding: Encoding) -> &mut Self {
+        if let Some(slot) = self.mnemonics.get_mut(mnemonic as usize) {
+            *slot = encoding;
+        }
+        self
+    }
+
+    pub fn set_data_flow(&mut self, flow: DataFlow, encoding: Encoding) -> &mut Self {
+        self.data_flows[flow as usize] = encoding;
+        self
+    }
+}
+
+impl Mnemonic {
+    /// Returns the decoder page the default [`IsaTable`] places this mnemonic
+    /// in. The data flow may still move the full instruction to another page,
+    /// as `mov al, 1h` does.
+    pub fn decoder_page(self) -> usize {
+        IsaTable::default().mnemonic(self).2
+    }
+}
+
 /// The container for a full instruction set. See the [module's](self) documentation for
 /// a more detailed description.
-#[derive(Debug, Default)]
+#[derive(Copy, Clone, Debug, Default)]
 pub struct Instruction {
     decoder_page: usize,
     main: u8,
@@ -97,7 +238,7 @@ pub struct Instruction {
 }
 
 impl Instruction {
-    const DECODER_PAGE_TURN: u8 = 0b_0000_0111;
+    pub(crate) const DECODER_PAGE_TURN: u8 = 0b_0000_0111;
 
     /// Returns a new and unencoded `Instruction`.
     pub const fn new() -> Self {
@@ -160,29 +301,14 @@ impl Instruction {
     /// # Examples
     ///
     /// TODO: add example where the further encoding is needed
-    pub fn encode_mnemonic(mut self, mnemonic: Mnemonic) -> Self {
-        use Mnemonic::*;
-        let (and, or, page) = match mnemonic {
-            // ALU
-            Add => (0b_000_11_111, 0b_000_00_000, 0),
-            Sub => (0b_001_11_111, 0b_001_00_000, 0),
-            And => (0b_010_11_111, 0b_010_00_000, 0),
-            Or => (0b_011_11_111, 0b_011_00_000, 0),
-            Xor => (0b_100_11_111, 0b_100_00_000, 0),
-            Not => (0b_101_11_111, 0b_101_00_000, 0),
-            Mov => (0b_110_11_111, 0b_110_00_000, 0),
-            Inc => (0b_111_11_111, 0b_111_00_000, 0),
-            // Flow control
-            Jmp => (0b_111_11_011, 0b_000_00_011, 1),
-            Jmpc => (0b_111_11_100, 0b_000_00_100, 1),
-            Jmpz => (0b_111_11_101, 0b_000_00_101, 1),
-            Call => (0b_111_11_110, 0b_000_00_110, 1),
-            Ret => (0b_111_11_000, 0b_000_00_000, 2),
-            Push => (0b_111_11_011, 0b_000_00_011, 2),
-            Pop => (0b_111_11_100, 0b_000_00_100, 2),
-            Pusha => (0b_111_11_101, 0b_000_00_101, 2),
-            Popa => (0b_111_11_110, 0b_000_00_110, 2),
-        };
+    pub fn encode_mnemonic(self, mnemonic: Mnemonic) -> Self {
+        self.encode_mnemonic_with(mnemonic, &IsaTable::default())
+    }
+
+    /// Same as [`Self::encode_mnemonic`](Self::encode_mnemonic), but with the
+    /// encodings taken from `isa`.
+    pub fn encode_mnemonic_with(mut self, mnemonic: Mnemonic, isa: &IsaTable) -> Self {
+        let (and, or, page) = isa.mnemonic(mnemonic);
         self.decoder_page = page;
         self.encode_main(and, or)
     }
@@ -198,30 +324,49 @@ impl Instruction {
     ///
     /// TODO: example where further encoding is necessary
     pub fn try_encode_data_flow(
+        self,
+        origin: &Primitive,
+        dest: &Primitive,
+    ) -> Result<Instruction, ErrorCode> {
+        self.try_encode_data_flow_with(origin, dest, &IsaTable::default())
+    }
+
+    /// Same as [`Self::try_encode_data_flow`](Self::try_encode_data_flow),
+    /// but with the encodings taken from `isa`.
+    pub fn try_encode_data_flow_with(
         mut self,
         origin: &Primitive,
         dest: &Primitive,
+        isa: &IsaTable,
     ) -> Result<Instruction, ErrorCode> {
+        use DataFlow::*;
         use ErrorCode::*;
-        let (and, or, page) = match origin {
+        // Ports only go one way, which deserves a clearer error than a flow
+        // that is merely unsupported
+        match (origin, dest) {
+            (Primitive::Port(Port::Output(_)), _) => return Err(ReadFromOutput),
+            (_, Primitive::Port(Port::Input(_))) => return Err(WriteToInput),
+            _ => (),
+        }
+        let flow = match origin {
             // Accumulator origin
             Primitive::Accumulator => match dest {
-                Primitive::Accumulator => (0b_111_11_000, 0b_000_00_000, 0),
+                Primitive::Accumulator => AccToAcc,
                 Primitive::Register(reg) => {
                     self = self.encode_register(*reg);
-                    (0b_111_11_001, 0b_000_00_001, 0)
+                    AccToRegister
                 }
                 Primitive::Memory(ram) => {
                     self.ram = Some(*ram);
-                    (0b_111_11_010, 0b_000_00_010, 0)
+                    AccToRam
                 }
                 Primitive::Port(out @ Port::Output(_)) => {
                     self = self.encode_port(*out);
-                    (0b_111_11_011, 0b_000_00_011, 0)
+                    AccToOutput
                 }
                 Primitive::DynamicMemory(reg) => {
                     self = self.encode_register(*reg);
-                    (0b_111_11_010, 0b_000_00_010, 2)
+                    AccToDynamicRam
                 }
                 _ => return Err(BadDestination),
             },
@@ -230,7 +375,7 @@ impl Instruction {
             Primitive::Register(reg) => match dest {
                 Primitive::Accumulator => {
                     self = self.encode_register(*reg);
-                    (0b_111_11_100, 0b_000_00_100, 0)
+                    RegisterToAcc
                 }
                 _ => return Err(BadDestination),
             },
@@ -239,7 +384,7 @@ impl Instruction {
             Primitive::Memory(ram) => {
                 self.ram = Some(*ram);
                 match dest {
-                    Primitive::Accumulator => (0b_111_11_101, 0b_000_00_101, 0),
+                    Primitive::Accumulator => RamToAcc,
                     _ => return Err(BadDestination),
                 }
             }
@@ -248,7 +393,7 @@ impl Instruction {
             Primitive::Port(input @ Port::Input(_)) => {
                 self = self.encode_port(*input);
                 match dest {
-                    Primitive::Accumulator => (0b_111_11_110, 0b_000_00_110, 0),
+                    Primitive::Accumulator => InputToAcc,
                     _ => return Err(BadDestination),
                 }
             }
@@ -257,14 +402,14 @@ impl Instruction {
             Primitive::Number(rom) => {
                 self.rom = Some(*rom);
                 match dest {
-                    Primitive::Accumulator => (0b_111_11_000, 0b_000_00_000, 1),
+                    Primitive::Accumulator => RomToAcc,
                     Primitive::Register(reg) => {
                         self = self.encode_register(*reg);
-                        (0b_111_11_001, 0b_000_00_001, 1)
+                        RomToRegister
                     }
                     Primitive::Memory(ram) => {
                         self.ram = Some(*ram);
-                        (0b_111_11_010, 0b_000_00_010, 1)
+                        RomToRam
                     }
                     _ => return Err(BadDestination),
                 }
@@ -274,7 +419,7 @@ impl Instruction {
             Primitive::DynamicMemory(reg) => {
                 self = self.encode_register(*reg);
                 match dest {
-                    Primitive::Accumulator => (0b_111_11_001, 0b_000_00_001, 2),
+                    Primitive::Accumulator => DynamicRamToAcc,
                     _ => return Err(BadDestination),
                 }
             }
@@ -282,10 +427,115 @@ impl Instruction {
             // Other origins
             _ => return Err(BadOrigin),
         };
+        let (and, or, page) = isa.data_flow(flow);
         self.decoder_page = page;
         Ok(self.encode_main(and, or))
     }
 
+    /// Sets the RAM address, as a jump's target, once it is known.
+    pub(crate) fn encode_ram(mut self, ram: u16) -> Self {
+        self.ram = Some(ram);
+        self
+    }
+
+    /// Returns the decoder page this `Instruction` lives in, i.e., how many
+    /// page turns precede its main byte.
+    pub const fn decoder_page(&self) -> usize {
+        self.decoder_page
+    }
+
+    /// Reads back the instruction at the start of `bytes`, as a simulator's
+    /// decode stage would, along with the amount of bytes it takes. Jump
+    /// targets are kept as the instruction's RAM address.
+    pub fn decode(bytes: &[u8], isa: &IsaTable) -> Option<(Self, usize)> {
+        let (_, len) = disassembly::decode(bytes, isa)?;
+        let decoder_page = bytes
+            .iter()
+            .take_while(|&&byte| byte == Self::DECODER_PAGE_TURN)
+            .count();
+        // The ROM byte, if any, comes before the RAM address
+        let (rom, ram) = match bytes[decoder_page + 1..len] {
+            [] => (None, None),
+            [rom] => (Some(rom), None),
+            [high, low] => (None, Some(u16::from_be_bytes([high, low]))),
+            [rom, high, low] => (Some(rom), Some(u16::from_be_bytes([high, low]))),
+            _ => unreachable!("instruction longer than its operands"),
+        };
+        let inst = Self {
+            decoder_page,
+            main: bytes[decoder_page],
+            rom,
+            ram,
+        };
+        Some((inst, len))
+    }
+
+    /// The mnemonic this `Instruction` was encoded from, if it forms a whole
+    /// instruction. `nop` is given as the `mov` it stands for.
+    pub fn mnemonic(&self) -> Option<Mnemonic> {
+        self.mnemonic_with(&IsaTable::default())
+    }
+
+    /// Same as [`Self::mnemonic`](Self::mnemonic), but with the encodings
+    /// taken from `isa`.
+    pub fn mnemonic_with(&self, isa: &IsaTable) -> Option<Mnemonic> {
+        self.decoded(isa).map(|(mnemonic, _)| mnemonic)
+    }
+
+    /// The destination and origin operands this `Instruction` was encoded
+    /// with, in that order. Jump targets are given as a
+    /// [`Primitive::Memory`](Primitive::Memory), and only when the
+    /// instruction holds its address.
+    pub fn operands(&self) -> (Option<Primitive>, Option<Primitive>) {
+        self.operands_with(&IsaTable::default())
+    }
+
+    /// Same as [`Self::operands`](Self::operands), but with the encodings
+    /// taken from `isa`.
+    pub fn operands_with(&self, isa: &IsaTable) -> (Option<Primitive>, Option<Primitive>) {
+        match self.decoded(isa) {
+            Some((_, [dest, origin])) => (dest, origin),
+            None => (None, None),
+        }
+    }
+
+    fn decoded(&self, isa: &IsaTable) -> Option<(Mnemonic, [Option<Primitive>; 2])> {
+        // Nothing but the bytes held are read, however far it was encoded
+        let bytes = unsafe { self.as_bytes() };
+        match disassembly::decode(&bytes, isa)? {
+            (DecodedKind::Instruction(mnemonic, operands), len) if len == bytes.len() => {
+                Some((mnemonic, operands))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the amount of bytes this `Instruction` takes once encoded,
+    /// without allocating them.
+    ///
+    /// The same guarantees as [`Self::as_bytes`](Self::as_bytes) apply: the
+    /// result is only meaningful for a fully encoded `Instruction`.
+    pub const fn byte_len(&self) -> usize {
+        self.decoder_page + 1 + self.rom.is_some() as usize + 2 * self.ram.is_some() as usize
+    }
+
+    /// Return the underlaying bytes corresponding to this `Instruction`, as
+    /// long as it does not require turning the decoder past `max_page`.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`Self::as_bytes`](Self::as_bytes) apply.
+    pub unsafe fn try_as_bytes(self, max_page: usize) -> Result<Vec<u8>, ErrorCode> {
+        if self.decoder_page > max_page {
+            Err(ErrorCode::UnsupportedInstruction(
+                self.decoder_page,
+                max_page,
+            ))
+        } else {
+            Ok(self.as_bytes())
+        }
+    }
+
     /// Return the underlaying bytes corresponding to this `Instruction`.
     ///
     /// # Safety
@@ -302,7 +552,7 @@ impl Instruction {
     pub unsafe fn as_bytes(self) -> Vec<u8> {
         use std::iter;
         let page = self.decoder_page;
-        let mut vec = Vec::with_capacity(page + 1);
+        let mut vec = Vec::with_capacity(self.byte_len());
         vec.extend(iter::repeat(Self::DECODER_PAGE_TURN).take(page));
         vec.push(self.main);
         if let Some(byte) = self.rom {
@@ -310,7 +560,6 @@ impl Instruction {
         }
         if let Some(word) = self.ram {
             let bytes = word.to_be_bytes();
-            vec.reserve(2);
             vec.push(bytes[0]);
             vec.push(bytes[1]);
         }
@@ -1,2 +1,4 @@
+pub mod disassembly;
+pub mod expression;
 pub mod instruction;
 pub mod token;
@@ -1,23 +1,36 @@
+use super::expression::is_expression;
 use enum_utils::FromStr;
-use std::{ops::Range, str::FromStr, sync::mpsc::Sender};
+use std::{convert::TryFrom, fmt, ops::Range, str::FromStr, sync::mpsc::Sender};
 use thiserror::Error;
 
 #[derive(Copy, Clone, Error, Debug)]
 pub enum TokenizingError {
     #[error("Unsupported architecture")]
     BadArchitecture,
+    #[error("Unknown directive")]
+    BadDirective,
     #[error("Malformed label")]
     BadLabel,
     #[error("Malformed memory location")]
     BadMemory,
     #[error("Malformed number")]
     BadNumber,
+    #[error("Unknown escape sequence")]
+    BadEscape,
     #[error("Unsupported port")]
     BadPort,
+    #[error("Number too large")]
+    ByteOverflow,
+    #[error("Descending range")]
+    DescendingRange,
     #[error("High byte used")]
     HighByte,
+    #[error("Non-ASCII character")]
+    NonAscii,
     #[error("Could not form a token")]
     UnknownToken,
+    #[error("Unterminated literal")]
+    UnterminatedLiteral,
 }
 
 impl TokenizingError {
@@ -25,12 +38,18 @@ impl TokenizingError {
         use TokenizingError::*;
         match self {
             BadArchitecture => "only 8-bits architecture is supported",
+            BadDirective => "the available directives are `.assert`, `.db`, `.dw`, `.equ`, `.jmptable`, `.org`, and `.if`, `.else`, `.endif`",
             BadLabel => "valid labels are formed by letters, numbers, and underscores; and may not start with numbers",
             BadMemory => "only number literals and registers may be memory locations",
-            BadNumber => "number literals must start with a digit. Decimals may have a trailing `d`. Hexadecimals must either start with `0x` or end with an `h`; binaries with `0b` or `b`.",
+            BadNumber => "number literals must start with a digit. Decimals are written as they are, as in `10`, or with a trailing `d`. Hexadecimals must either start with `0x` or end with an `h`; binaries with `0b` or `b`.",
+            BadEscape => "the available escapes are `\\n`, `\\r`, `\\t`, `\\0`, `\\\\`, `\\'`, and `\\\"`",
             BadPort => "only I/O ports from 0 to 3 are currently supported",
+            ByteOverflow => "number literals must fit in a byte, from `0` up to `255` or `0xff`, or in a word up to `0xffff` for `.dw`",
+            DescendingRange => "ranges must go from the lowest to the highest byte, as in `'a'..'z'`",
             HighByte => "use the lower byte, by switching from `h` to `l`",
-            UnknownToken => "???"
+            NonAscii => "only ASCII characters fit in a single byte",
+            UnknownToken => "this is not a valid mnemonic, register, port, or label",
+            UnterminatedLiteral => "close the literal with the quote it starts with, before the line ends"
         }
     }
 }
@@ -39,9 +58,31 @@ pub struct EOL;
 
 pub type TokenSender = Sender<Result<Token, EOL>>;
 
+/// Where the lexer hands the tokens it forms over to.
+pub trait TokenSink {
+    fn send_token(&mut self, token: Result<Token, EOL>);
+}
+
+impl TokenSink for TokenSender {
+    fn send_token(&mut self, token: Result<Token, EOL>) {
+        // The parser may have stopped on an error, but the tokens must still
+        // be formed so that tokenizing errors are always found
+        self.send(token).ok();
+    }
+}
+
+impl TokenSink for Vec<Result<Token, EOL>> {
+    fn send_token(&mut self, token: Result<Token, EOL>) {
+        self.push(token);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
+    /// The characters of its line the token covers, counted from `0`. Tabs
+    /// count as a single character, and are only expanded to the next tab
+    /// stop when an error is rendered.
     pub span: Range<usize>,
     pub line: usize,
 }
@@ -51,7 +92,7 @@ impl Token {
         src: &mut String,
         span: Range<usize>,
         line: usize,
-        channel: &TokenSender,
+        channel: &mut impl TokenSink,
     ) -> Result<(), (Self, TokenizingError)> {
         if src.is_empty() {
             return Ok(());
@@ -67,9 +108,7 @@ impl Token {
             )
         })?;
         src.clear();
-        channel
-            .send(Ok(Self { kind, span, line }))
-            .expect("parser stopped unexpectedly");
+        channel.send_token(Ok(Self { kind, span, line }));
         Ok(())
     }
 }
@@ -81,10 +120,15 @@ pub enum TokenKind {
     Label(String),
     /// See [`Mnemonic`](Mnemonic) for all available mnemonics
     Mnemonic(Mnemonic),
+    /// See [`Directive`](Directive) for all available directives
+    Directive(Directive),
     /// See [`Primitive`](Primitive) for all available primitives
     Operand(Primitive),
     /// The operands' separator
     Comma,
+    /// The text after a `;`, only kept when asked for with
+    /// [`Lexer::keep_comments`](crate::Lexer::keep_comments)
+    Comment(String),
     /// An unknown token
     Error,
 }
@@ -96,12 +140,17 @@ impl FromStr for TokenKind {
         Ok(match src {
             "," => Self::Comma,
             _ if src.ends_with(':') => {
-                if Primitive::is_label(&src[..src.len() - 1]) {
-                    Self::Label(src.into())
+                let label = &src[..src.len() - 1];
+                if Primitive::is_label(label) || Primitive::is_local_label(label) {
+                    Self::Label(label.into())
                 } else {
                     return Err(TokenizingError::BadLabel);
                 }
             }
+            _ if src.starts_with('.') => match src[1..].parse() {
+                Ok(directive) => Self::Directive(directive),
+                _ => return Err(TokenizingError::BadDirective),
+            },
             _ => match src.parse() {
                 Ok(mnemonic) => Self::Mnemonic(mnemonic),
                 _ => Self::Operand(src.parse()?),
@@ -127,11 +176,13 @@ pub enum Mnemonic {
     /// stores it in the destination
     Xor,
     /// Performs a bitwise not operation on a value and stores it in the
-    /// destination
+    /// destination. A single operand may only be the accumulator, which is
+    /// then changed in place.
     Not,
     /// Copies a value to a destination
     Mov,
-    /// Increments a value by `1` and stores it in the destination
+    /// Increments a value by `1` and stores it in the destination. A single
+    /// operand may only be the accumulator, which is then changed in place.
     Inc,
     /// Redirects the flow of operation to a memory location
     Jmp,
@@ -156,20 +207,168 @@ pub enum Mnemonic {
     Pusha,
     /// Pops the stack and stores the value into the accumulator
     Popa,
+    /// Does nothing, being encoded as `mov al, al`
+    Nop,
+    /// Redirects the flow of operation to a memory location if the last
+    /// subtraction did not borrow nor result in `0`, i.e., the accumulator
+    /// was above what was subtracted, both taken as unsigned. A pseudo
+    /// instruction expanding into `jc skip`, `jz skip`, and `jmp label`, with
+    /// `skip` right after them, which leaves the accumulator and the flags
+    /// untouched. The flags tested are those of the last instruction that set
+    /// them, so a `sub` is to be written right before.
+    Ja,
+    /// Redirects the flow of operation to a memory location if the last
+    /// subtraction borrowed, i.e., the accumulator was below what was
+    /// subtracted, both taken as unsigned. A pseudo instruction expanding into
+    /// `jc label`.
+    Jb,
 }
 
 impl Mnemonic {
+    pub(crate) const ALL: [Self; 18] = [
+        Self::Add,
+        Self::Sub,
+        Self::Or,
+        Self::And,
+        Self::Xor,
+        Self::Not,
+        Self::Mov,
+        Self::Inc,
+        Self::Jmp,
+        Self::Jmpc,
+        Self::Jmpz,
+        Self::Call,
+        Self::Ret,
+        Self::Push,
+        Self::Pop,
+        Self::Pusha,
+        Self::Popa,
+        Self::Nop,
+    ];
+
+    /// The pseudo instructions, which expand into others and have no slot of
+    /// their own in an [`IsaTable`](super::instruction::IsaTable).
+    pub(crate) const PSEUDO: [Self; 2] = [Self::Ja, Self::Jb];
+
+    /// The mnemonic whose encoding this one takes: the first instruction a
+    /// pseudo instruction expands into, or else the mnemonic itself.
+    pub(crate) fn encoded_as(self) -> Self {
+        match self {
+            Self::Ja | Self::Jb => Self::Jmpc,
+            _ => self,
+        }
+    }
+
     pub(crate) fn operands_required(self) -> usize {
         use Mnemonic::*;
         match self {
             Add | Sub | Or | And | Xor | Not | Mov | Inc => 2,
-            Jmp | Jmpc | Jmpz | Call | Push | Pop => 1,
+            Jmp | Jmpc | Jmpz | Call | Push | Pop | Ja | Jb => 1,
             _ => 0,
         }
     }
+
+    /// Whether this mnemonic takes the address it goes to as its operand.
+    pub(crate) fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Self::Jmp | Self::Jmpc | Self::Jmpz | Self::Call | Self::Ja | Self::Jb
+        )
+    }
+
+    /// Whether this mnemonic sets the `C` and the `Z` flags, in that order.
+    /// Those it does not set are left as they were.
+    pub fn affects_flags(self) -> (bool, bool) {
+        use Mnemonic::*;
+        match self {
+            Add | Sub | Inc => (true, true),
+            And | Or | Xor | Not => (false, true),
+            _ => (false, false),
+        }
+    }
+
+    /// Whether the origin may be left out, taking the accumulator in its place.
+    pub(crate) fn is_unary(self) -> bool {
+        matches!(self, Self::Not | Self::Inc)
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+impl fmt::Display for Mnemonic {
+    /// Writes the single spelling used for this mnemonic when disassembling,
+    /// even if others are accepted, such as `je` for `jz`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Mnemonic::*;
+        f.write_str(match self {
+            Add => "add",
+            Sub => "sub",
+            Or => "or",
+            And => "and",
+            Xor => "xor",
+            Not => "not",
+            Mov => "mov",
+            Inc => "inc",
+            Jmp => "jmp",
+            Jmpc => "jc",
+            Jmpz => "jz",
+            Call => "call",
+            Ret => "ret",
+            Push => "push",
+            Pop => "pop",
+            Pusha => "pusha",
+            Popa => "popa",
+            Nop => "nop",
+            Ja => "ja",
+            Jb => "jb",
+        })
+    }
+}
+
+/// Assembler directives, written with a leading `.`, which emit data rather
+/// than instructions
+#[derive(Copy, Clone, PartialEq, Debug, FromStr)]
+#[enumeration(case_insensitive)]
+pub enum Directive {
+    /// Emits each of its comma separated operands as a single byte, or every
+    /// byte of an inclusive range
+    Db,
+    /// Emits each of its comma separated operands as a big-endian word, be it
+    /// a number, an expression, or the address of a label, numeric ones
+    /// included. Negative values wrap around, as in `-1h` for `0ffffh`.
+    Dw,
+    /// Fails the assembly if its condition, written without spaces as in
+    /// `$<100h`, does not hold, with its optional quoted text as the message
+    Assert,
+    /// Defines the constant named by its first operand as its second one
+    Equ,
+    /// Emits the two byte address of each of its comma separated labels
+    JmpTable,
+    /// Moves on to the address given by its operand, leaving a gap behind
+    Org,
+    /// Starts a block that is only assembled if its operand, a number or the
+    /// name of a define, is not `0`
+    If,
+    /// Starts the part of a `.if` block assembled when its condition fails
+    Else,
+    /// Ends a `.if` block
+    Endif,
+}
+
+impl Directive {
+    #[cfg(test)]
+    pub(crate) const ALL: [Self; 9] = [
+        Self::Db,
+        Self::Dw,
+        Self::Assert,
+        Self::Equ,
+        Self::JmpTable,
+        Self::Org,
+        Self::If,
+        Self::Else,
+        Self::Endif,
+    ];
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Port {
     /// Input ports 0 through 3
     Input(u8),
@@ -194,6 +393,15 @@ impl FromStr for Port {
     }
 }
 
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Input(port) => write!(f, "in{}", port),
+            Self::Output(port) => write!(f, "out{}", port),
+        }
+    }
+}
+
 impl Port {
     pub(super) fn port_number(self) -> u8 {
         match self {
@@ -203,7 +411,7 @@ impl Port {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Register {
     /// Register B `bl`
     B,
@@ -232,11 +440,25 @@ impl FromStr for Register {
     }
 }
 
-#[derive(Clone, Debug)]
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::B => "bl",
+            Self::C => "cl",
+            Self::D => "dl",
+            Self::E => "el",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Primitive {
     /// An ASCII character surrounded by single or double quotes, or a sequence
-    /// that matches `[+-]?(\d+d?|\d[\da-f]*h|0x[\da-f]+|[01]+b|0b[01]+)`
+    /// that matches `\+?(\d+d?|\d[\da-f]*h|0x[\da-f]+|[01]+b|0b[01]+)`.
+    /// Negative numbers are read as a `Self::Expression`.
     Number(u8),
+    /// A number too large for a byte, which only `.dw` takes
+    Word(u16),
     /// An I/O port
     Port(Port),
     /// A register
@@ -251,6 +473,45 @@ pub enum Primitive {
     DynamicMemoryAccumulator,
     /// A memory location label matching the regex `\w+`
     Label(String),
+    /// A reference to the nearest numeric label with the given number, and
+    /// whether it is looked for after the reference, as in `1f`, rather than
+    /// before it, as in `1b`. Outside of jumps and `.jmptable`, `1b` is read as
+    /// the binary number it also spells.
+    LocalLabel(u32, bool),
+    /// An inclusive range between two `Self::Number`s separated by `..`, as
+    /// in `'a'..'z'`. Descending ranges are rejected.
+    Range(u8, u8),
+    /// Numbers and constants joined by `+`, `-`, and `*`, or a negative
+    /// number, which is folded into a `Self::Number` once the constants are
+    /// known, or into a `Self::Word` where one is taken
+    Expression(String),
+    /// Any amount of characters other than one surrounded by single or double
+    /// quotes, kept as they were written
+    Text(String),
+}
+
+/// Replaces the escape sequences in the text of a quoted literal by the
+/// characters they stand for.
+fn unescape(src: &str) -> Result<String, TokenizingError> {
+    let mut text = String::with_capacity(src.len());
+    let mut chars = src.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            text.push(ch);
+            continue;
+        }
+        text.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(ch @ ('\\' | '\'' | '"')) => ch,
+            // The closing quote itself was escaped
+            None => return Err(TokenizingError::UnterminatedLiteral),
+            Some(_) => return Err(TokenizingError::BadEscape),
+        });
+    }
+    Ok(text)
 }
 
 impl FromStr for Primitive {
@@ -258,25 +519,58 @@ impl FromStr for Primitive {
 
     fn from_str(src: &str) -> Result<Self, Self::Err> {
         use TokenizingError::*;
+        if let Some(idx) = src.find("..") {
+            return match (Self::literal(&src[..idx])?, Self::literal(&src[idx + 2..])?) {
+                (Self::Number(start), Self::Number(end)) if start <= end => {
+                    Ok(Self::Range(start, end))
+                }
+                (Self::Number(_), Self::Number(_)) => Err(DescendingRange),
+                (Self::Word(_), _) | (_, Self::Word(_)) => Err(ByteOverflow),
+                _ => Err(BadNumber),
+            };
+        }
+        if is_expression(src) {
+            return Ok(Self::Expression(src.into()));
+        }
         Ok(match src.as_bytes() {
-            // Raw number
-            [b'+', head, ..] | [b'-', head, ..] | [head, ..]
-                if (*head as char).is_ascii_digit() =>
+            // Local label reference
+            [digits @ .., b'b'] | [digits @ .., b'f']
+                if Self::is_local_label(&src[..digits.len()]) =>
             {
-                let num = try_to_number(src).ok_or(BadNumber)?;
-                Self::Number(num)
+                match src[..digits.len()].parse() {
+                    Ok(number) => Self::LocalLabel(number, src.ends_with('f')),
+                    Err(_) => Self::number(src)?,
+                }
+            }
+            // Negative numbers are folded where they are used, so that they
+            // wrap around to a word where one is taken, as in `.dw -1h`
+            [b'-', head, ..] if (*head as char).is_ascii_digit() => {
+                Self::number(&src[1..])?;
+                Self::Expression(src.into())
+            }
+            // Raw number
+            [b'+', head, ..] | [head, ..] if (*head as char).is_ascii_digit() => Self::number(src)?,
+            // ASCII character or text
+            [quote @ (b'"' | b'\''), ..] => {
+                if src.len() < 2 || !src.ends_with(*quote as char) {
+                    return Err(UnterminatedLiteral);
+                }
+                let text = unescape(&src[1..src.len() - 1])?;
+                match text.as_bytes() {
+                    [ch] if ch.is_ascii() => Self::Number(*ch),
+                    _ => Self::Text(text),
+                }
             }
-            // ASCII character
-            [b'"', ch, b'"'] | [b'\'', ch, b'\''] if (*ch as char).is_ascii() => Self::Number(*ch),
             // Accumulator
             b"rax" | b"eax" | b"ax" => return Err(BadArchitecture),
             b"ah" => return Err(HighByte),
             b"al" => Self::Accumulator,
             // Memory location
+            b"[]" => return Err(BadMemory),
             [b'[', mem @ .., b']'] => unsafe {
                 // Given that `src` is a valid `&str`, therefore `mem` must be
                 // as well, making this operation safe
-                match std::str::from_utf8_unchecked(mem).parse::<Self>() {
+                match Self::literal(std::str::from_utf8_unchecked(mem)) {
                     Ok(Self::Number(byte)) => Self::Memory(byte as u16),
                     Ok(Self::Register(reg)) => Self::DynamicMemory(reg),
                     Ok(Self::Accumulator) => Self::DynamicMemoryAccumulator,
@@ -292,7 +586,7 @@ impl FromStr for Primitive {
                 _ => match src.parse() {
                     Ok(reg) => Self::Register(reg),
                     Err(UnknownToken) if Self::is_label(src) => Self::Label(src.into()),
-                    Err(UnknownToken) => return Err(BadLabel),
+                    Err(UnknownToken) => return Err(UnknownToken),
                     Err(err) => return Err(err),
                 },
             },
@@ -300,7 +594,85 @@ impl FromStr for Primitive {
     }
 }
 
+impl From<u8> for Primitive {
+    fn from(byte: u8) -> Self {
+        Self::Number(byte)
+    }
+}
+
+/// The character's ASCII code, as the quoted character would amount to.
+impl TryFrom<char> for Primitive {
+    type Error = TokenizingError;
+
+    fn try_from(ch: char) -> Result<Self, Self::Error> {
+        if ch.is_ascii() {
+            Ok(Self::Number(ch as u8))
+        } else {
+            Err(TokenizingError::NonAscii)
+        }
+    }
+}
+
+impl fmt::Display for Primitive {
+    /// Writes this operand back as source, with numbers in hexadecimal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Number(byte) => write!(f, "0{:02x}h", byte),
+            Self::Word(word) => write!(f, "0{:04x}h", word),
+            Self::LocalLabel(number, forward) => {
+                write!(f, "{}{}", number, if *forward { 'f' } else { 'b' })
+            }
+            Self::Port(port) => write!(f, "{}", port),
+            Self::Register(reg) => write!(f, "{}", reg),
+            Self::Accumulator => f.write_str("al"),
+            Self::Memory(addr) => write!(f, "[0{:02x}h]", addr),
+            Self::DynamicMemory(reg) => write!(f, "[{}]", reg),
+            Self::DynamicMemoryAccumulator => f.write_str("[al]"),
+            Self::Label(label) | Self::Expression(label) => f.write_str(label),
+            Self::Range(start, end) => write!(f, "0{:02x}h..0{:02x}h", start, end),
+            Self::Text(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
 impl Primitive {
+    /// Reads a number literal, which is only a `Self::Word` if it does not
+    /// fit in a byte.
+    fn number(src: &str) -> Result<Self, TokenizingError> {
+        match try_to_number(src) {
+            Ok(num) => Ok(Self::Number(num)),
+            Err(TokenizingError::ByteOverflow) => match try_to_word(src) {
+                Some(word) => Ok(Self::Word(word)),
+                None => Err(TokenizingError::ByteOverflow),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Parses `src`, where a number is expected rather than a local label
+    /// reference.
+    fn literal(src: &str) -> Result<Self, TokenizingError> {
+        match src.parse()? {
+            Self::LocalLabel(number, forward) => Self::local_as_number(number, forward),
+            Self::Expression(_) if src.starts_with('-') => Self::number(src),
+            primitive => Ok(primitive),
+        }
+    }
+
+    /// Reads a backward local label reference as the binary number it also
+    /// spells, as `101b` for `5`.
+    pub(crate) fn local_as_number(number: u32, forward: bool) -> Result<Self, TokenizingError> {
+        if forward {
+            return Err(TokenizingError::BadNumber);
+        }
+        Self::number(&format!("{}b", number))
+    }
+
+    /// Whether `src` names a numeric label, as in `1:`.
+    pub(crate) fn is_local_label(src: &str) -> bool {
+        !src.is_empty() && src.bytes().all(|byte| byte.is_ascii_digit())
+    }
+
     fn is_label(src: &str) -> bool {
         if src.starts_with(|ch: char| ch.is_ascii_digit()) {
             return false;
@@ -310,30 +682,57 @@ impl Primitive {
     }
 }
 
-fn try_to_number(mut src: &str) -> Option<u8> {
-    fn fold_byte(src: &[u8], radix: u32) -> Option<u8> {
-        use std::convert::TryInto;
-        let mut num = 0;
-        for &byte in src {
-            num = num * radix + (byte as char).to_digit(radix)?;
-        }
-        num.try_into().ok()
-    }
+fn try_to_number(mut src: &str) -> Result<u8, TokenizingError> {
+    use std::convert::TryInto;
     let is_complement = src.starts_with('-');
     if is_complement || src.starts_with('+') {
         src = &src[1..];
     }
-    let byte = match src.as_bytes() {
-        [head @ .., b'b'] => fold_byte(head, 2),
-        [head @ .., b'd'] => fold_byte(head, 10),
-        [head @ .., b'h'] => fold_byte(head, 16),
-        [b'0', b'b', tail @ ..] => fold_byte(tail, 2),
-        [b'0', b'x', tail @ ..] => fold_byte(tail, 16),
-        _ => None,
+    let byte: u8 = match try_to_word(src) {
+        Some(word) => word.try_into().map_err(|_| TokenizingError::ByteOverflow)?,
+        // Well formed literals only fail to fold when they are too large
+        None => match split_radix(src) {
+            Some((digits, radix))
+                if !digits.is_empty()
+                    && digits.iter().all(|&digit| (digit as char).is_digit(radix)) =>
+            {
+                return Err(TokenizingError::ByteOverflow)
+            }
+            _ => return Err(TokenizingError::BadNumber),
+        },
     };
     if is_complement {
-        byte.map(|b| b.overflowing_neg().0)
+        Ok(byte.overflowing_neg().0)
     } else {
-        byte
+        Ok(byte)
+    }
+}
+
+/// Splits an unsigned number literal into its digits and radix. Digits with
+/// no prefix or suffix are decimal, wherever a number is taken.
+fn split_radix(src: &str) -> Option<(&[u8], u32)> {
+    if !src.starts_with(|ch: char| ch.is_ascii_digit()) {
+        return None;
+    }
+    Some(match src.as_bytes() {
+        [head @ .., b'b'] => (head, 2),
+        [head @ .., b'd'] => (head, 10),
+        [head @ .., b'h'] => (head, 16),
+        [b'0', b'b', tail @ ..] => (tail, 2),
+        [b'0', b'x', tail @ ..] => (tail, 16),
+        digits => (digits, 10),
+    })
+}
+
+/// Parses an unsigned number literal of up to two bytes.
+pub(crate) fn try_to_word(src: &str) -> Option<u16> {
+    use std::convert::TryInto;
+    let (digits, radix) = split_radix(src)?;
+    let mut num = 0u32;
+    for &digit in digits {
+        num = num
+            .checked_mul(radix)?
+            .checked_add((digit as char).to_digit(radix)?)?;
     }
+    num.try_into().ok()
 }
@@ -0,0 +1,203 @@
+//! A reference interpreter for the instruction set described in the
+//! [`instruction`](crate::intel::instruction) module, for checking what an
+//! assembled program does without the processor at hand.
+//!
+//! The program is loaded into memory and fetched from it, so that data emitted
+//! alongside the code may be read back. Every ALU operation takes the
+//! accumulator and the value coming from its origin, and stores the result in
+//! its destination, updating the flags given by [`Mnemonic::affects_flags`].
+//! Return addresses are kept in a call stack of their own, apart from the
+//! values pushed.
+
+use crate::{intel::disassembly, DecodedKind, IsaTable, Mnemonic, Port, Primitive};
+
+/// Why the interpreter stopped before running out of steps.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Halt {
+    /// Execution ran past the end of the program
+    EndOfProgram,
+    /// A jump to itself, as in `end: jmp end`, which would loop forever
+    SelfJump,
+    /// A `ret` with no call to return from
+    ReturnFromTop,
+    /// The bytes at the program counter do not form an instruction
+    BadInstruction,
+}
+
+/// The state of the processor, as left by [`run`].
+#[derive(Clone, Debug)]
+pub struct MachineState {
+    /// The accumulator, `al`
+    pub acc: u8,
+    /// Registers B through E
+    pub registers: [u8; 4],
+    /// Whether the last ALU operation carried or borrowed
+    pub carry: bool,
+    /// Whether the result of the last ALU operation was `0`
+    pub zero: bool,
+    /// The address of the next instruction
+    pub pc: u16,
+    /// The whole address space, with the program loaded into it
+    pub memory: Vec<u8>,
+    /// The last value written to each output port
+    pub outputs: [u8; 4],
+    /// The values each input port reads as
+    pub inputs: [u8; 4],
+    /// The values pushed and not yet popped
+    pub stack: Vec<u8>,
+    /// The addresses `ret` goes back to
+    pub call_stack: Vec<u16>,
+    /// How many instructions were executed
+    pub steps: usize,
+    /// Why execution stopped, unless it ran out of steps
+    pub halt: Option<Halt>,
+}
+
+impl MachineState {
+    /// A processor with `program` loaded at `base`, about to run it.
+    pub fn new(program: &[u8], base: u16) -> Self {
+        let mut memory = vec![0; 0x10000];
+        let start = base as usize;
+        let len = program.len().min(memory.len() - start);
+        memory[start..start + len].copy_from_slice(&program[..len]);
+        Self {
+            acc: 0,
+            registers: [0; 4],
+            carry: false,
+            zero: false,
+            pc: base,
+            memory,
+            outputs: [0; 4],
+            inputs: [0; 4],
+            stack: Vec::new(),
+            call_stack: Vec::new(),
+            steps: 0,
+            halt: None,
+        }
+    }
+
+    fn read(&self, operand: &Primitive) -> u8 {
+        match *operand {
+            Primitive::Accumulator => self.acc,
+            Primitive::Register(reg) => self.registers[reg as usize],
+            Primitive::Number(byte) => byte,
+            Primitive::Memory(address) => self.memory[address as usize],
+            Primitive::DynamicMemory(reg) => self.memory[self.registers[reg as usize] as usize],
+            Primitive::Port(Port::Input(num)) => self.inputs[num as usize],
+            _ => unreachable!("read from {:?}", operand),
+        }
+    }
+
+    fn write(&mut self, operand: &Primitive, value: u8) {
+        match *operand {
+            Primitive::Accumulator => self.acc = value,
+            Primitive::Register(reg) => self.registers[reg as usize] = value,
+            Primitive::Memory(address) => self.memory[address as usize] = value,
+            Primitive::DynamicMemory(reg) => {
+                let address = self.registers[reg as usize] as usize;
+                self.memory[address] = value;
+            }
+            Primitive::Port(Port::Output(num)) => self.outputs[num as usize] = value,
+            _ => unreachable!("write to {:?}", operand),
+        }
+    }
+
+    /// Executes the instruction at the program counter, if execution has
+    /// not halted already. `end` is the address the program ends at.
+    pub fn step(&mut self, end: usize, isa: &IsaTable) {
+        use Mnemonic::*;
+        if self.halt.is_some() {
+            return;
+        }
+        let pc = self.pc as usize;
+        if pc >= end {
+            self.halt = Some(Halt::EndOfProgram);
+            return;
+        }
+        let (mnemonic, [dest, origin], len) = match disassembly::decode(&self.memory[pc..], isa) {
+            Some((DecodedKind::Instruction(mnemonic, operands), len)) => (mnemonic, operands, len),
+            _ => {
+                self.halt = Some(Halt::BadInstruction);
+                return;
+            }
+        };
+        self.steps += 1;
+        let next = self.pc.wrapping_add(len as u16);
+        self.pc = next;
+        let target = match dest {
+            Some(Primitive::Memory(address)) => address,
+            _ => 0,
+        };
+        let jump = |state: &mut Self, taken: bool| {
+            if !taken {
+                return;
+            }
+            if target as usize == pc {
+                state.halt = Some(Halt::SelfJump);
+            }
+            state.pc = target;
+        };
+        match mnemonic {
+            Jmp => jump(self, true),
+            Jmpc => jump(self, self.carry),
+            Jmpz => jump(self, self.zero),
+            Call => {
+                self.call_stack.push(next);
+                jump(self, true);
+            }
+            Ret => match self.call_stack.pop() {
+                Some(address) => self.pc = address,
+                None => self.halt = Some(Halt::ReturnFromTop),
+            },
+            Push => {
+                let value = self.read(dest.as_ref().unwrap());
+                self.stack.push(value);
+            }
+            Pop => {
+                let value = self.stack.pop().unwrap_or(0);
+                self.write(dest.as_ref().unwrap(), value);
+            }
+            Pusha => self.stack.push(self.acc),
+            Popa => self.acc = self.stack.pop().unwrap_or(0),
+            _ => {
+                let (dest, origin) = (dest.unwrap(), origin.unwrap());
+                let (acc, value) = (self.acc, self.read(&origin));
+                let (result, carry) = match mnemonic {
+                    Add => acc.overflowing_add(value),
+                    Sub => acc.overflowing_sub(value),
+                    Inc => value.overflowing_add(1),
+                    And => (acc & value, false),
+                    Or => (acc | value, false),
+                    Xor => (acc ^ value, false),
+                    Not => (!value, false),
+                    _ => (value, false),
+                };
+                let (sets_carry, sets_zero) = mnemonic.affects_flags();
+                if sets_carry {
+                    self.carry = carry;
+                }
+                if sets_zero {
+                    self.zero = result == 0;
+                }
+                self.write(&dest, result);
+            }
+        }
+    }
+}
+
+/// Runs `program` from its first byte until it halts, or for `max_steps`
+/// instructions at most.
+pub fn run(program: &[u8], max_steps: usize) -> MachineState {
+    run_with(program, 0, &IsaTable::default(), max_steps)
+}
+
+/// Same as [`run`], but with the program loaded at `base` and decoded
+/// according to `isa`.
+pub fn run_with(program: &[u8], base: u16, isa: &IsaTable, max_steps: usize) -> MachineState {
+    let mut state = MachineState::new(program, base);
+    let end = base as usize + program.len();
+    while state.halt.is_none() && state.steps < max_steps {
+        state.step(end, isa);
+    }
+    state
+}
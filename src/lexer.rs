@@ -1,34 +1,378 @@
 use crate::intel::token::*;
 use crate::{AssemblyError, ErrorCode};
 
-pub fn scan(src: &str, channel: TokenSender) -> Result<(), AssemblyError> {
-    let mut token = String::new();
+/// Tokenizes sources one after the other, reusing its buffers rather than
+/// spawning a thread and opening a channel for each, as
+/// [`Assembly::assemble`](crate::Assembly::assemble) does.
+#[derive(Default)]
+pub struct Lexer {
+    token: String,
+    tokens: Vec<Result<Token, EOL>>,
+    keep_comments: bool,
+}
+
+impl Lexer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the text after each `;` is kept as a
+    /// [`TokenKind::Comment`](TokenKind::Comment) rather than dropped, as a
+    /// formatter would need. The parser ignores them either way.
+    pub fn keep_comments(mut self, keep: bool) -> Self {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// Tokenizes `src` into its tokens alone, each knowing its line.
+    pub fn tokenize(&mut self, src: &str) -> Result<Vec<Token>, AssemblyError> {
+        let tokens = self.scan(src)?;
+        Ok(tokens.drain(..).filter_map(Result::ok).collect())
+    }
+
+    /// Tokenizes `src`, handing back the tokens to be drained by the parser.
+    pub(crate) fn scan(
+        &mut self,
+        src: &str,
+    ) -> Result<&mut Vec<Result<Token, EOL>>, AssemblyError> {
+        let mut errors = Vec::new();
+        self.scan_recovering(src, &mut errors);
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(&mut self.tokens),
+        }
+    }
+
+    /// Tokenizes `src` as [`scan`](Self::scan) does, but collects every
+    /// error into `errors`, one per line at most.
+    pub(crate) fn scan_recovering(
+        &mut self,
+        src: &str,
+        errors: &mut Vec<AssemblyError>,
+    ) -> &mut Vec<Result<Token, EOL>> {
+        self.token.clear();
+        self.tokens.clear();
+        let keep_comments = self.keep_comments;
+        scan_into(
+            src,
+            &mut self.token,
+            &mut self.tokens,
+            keep_comments,
+            errors,
+        );
+        &mut self.tokens
+    }
+}
+
+pub fn scan(src: &str, mut channel: TokenSender) -> Result<(), AssemblyError> {
+    let mut errors = Vec::new();
+    scan_into(src, &mut String::new(), &mut channel, false, &mut errors);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Whether `line` ends with a backslash, which continues the statement on the
+/// next line. Backslashes within quotes or comments are left as they are.
+fn is_continued(line: &str) -> bool {
+    let line = line.trim_end();
+    let mut quote = None;
+    let mut escaped = false;
+    for ch in line.chars() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if ch == '\\' => escaped = true,
+            Some(open) if ch == open => quote = None,
+            Some(_) => (),
+            None if ch == ';' => return false,
+            None if ch == '\'' || ch == '"' => quote = Some(ch),
+            None => (),
+        }
+    }
+    quote.is_none() && line.ends_with('\\')
+}
+
+/// Tokenizes `src` into `channel`. The rest of a line is skipped after an
+/// error, but the lines after it are still scanned so the parser is kept in
+/// sync.
+fn scan_into(
+    src: &str,
+    token: &mut String,
+    channel: &mut impl TokenSink,
+    keep_comments: bool,
+    errors: &mut Vec<AssemblyError>,
+) {
+    let mut continues = false;
     for (mut line_no, line) in src.lines().enumerate() {
         line_no += 1;
+        continues = is_continued(line);
+        let line = match continues {
+            true => line.trim_end().strip_suffix('\\').unwrap_or(line),
+            false => line,
+        };
+        let line_len = line.chars().count();
+        let mut quote = None;
+        let mut escaped = false;
         // We chain as to always pack the token at the end of a line
         for (col, ch) in line.chars().chain(std::iter::once(' ')).enumerate() {
-            let span = col - token.len()..col;
+            // Spans count characters, as `col` does, rather than bytes
+            let span = col - token.chars().count()..col;
             let mut skip_rest_of_line = false;
+            // Quoted characters are taken verbatim, and quotes left open are
+            // closed by the end of the line. Escaped quotes do not close them.
+            if quote.is_some() && col < line_len {
+                token.push(ch);
+                if escaped {
+                    escaped = false;
+                } else if ch == '\\' {
+                    escaped = true;
+                } else if quote == Some(ch) {
+                    quote = None;
+                }
+                continue;
+            }
             let attempt = match ch {
+                '\'' | '"' => {
+                    token.push(ch);
+                    quote = Some(ch);
+                    Ok(())
+                }
                 ';' => {
                     skip_rest_of_line = true;
-                    Token::try_send(&mut token, span, line_no, &channel)
+                    Token::try_send(token, span, line_no, channel).map(|_| {
+                        if keep_comments {
+                            channel.send_token(Ok(Token {
+                                kind: TokenKind::Comment(line.chars().skip(col + 1).collect()),
+                                span: col..line_len,
+                                line: line_no,
+                            }));
+                        }
+                    })
                 }
-                ',' => {
-                    let res = Token::try_send(&mut token, span, line_no, &channel);
+                ',' => Token::try_send(token, span, line_no, channel).and_then(|_| {
+                    token.push(ch);
+                    Token::try_send(token, col..col + 1, line_no, channel)
+                }),
+                // A label ends with its colon, even if something follows
+                ':' => {
                     token.push(ch);
-                    Token::try_send(&mut token, col - 1..col, line_no, &channel).unwrap();
-                    res
+                    Token::try_send(token, span.start..col + 1, line_no, channel)
                 }
-                _ if ch.is_whitespace() => Token::try_send(&mut token, span, line_no, &channel),
+                _ if ch.is_whitespace() => Token::try_send(token, span, line_no, channel),
                 _ => Ok(token.push(ch.to_ascii_lowercase())),
             };
-            attempt.map_err(|(token, err)| AssemblyError::new(token, ErrorCode::Token(err)))?;
+            if let Err((err_token, err)) = attempt {
+                let code = match err {
+                    // The text is still in the buffer, since it failed to form
+                    TokenizingError::UnknownToken => ErrorCode::UnknownToken(token.clone()),
+                    err => ErrorCode::Token(err),
+                };
+                errors.push(AssemblyError::new(err_token, code));
+                // The rest of the line is skipped, so the parser can carry on
+                // from the next one
+                token.clear();
+                skip_rest_of_line = true;
+            }
             if skip_rest_of_line {
                 break;
             }
         }
-        channel.send(Err(EOL)).expect("parser stopped unexpectedly");
+        if !continues {
+            channel.send_token(Err(EOL));
+        }
+    }
+    // The last statement ends with the source, even if it was continued
+    if continues {
+        channel.send_token(Err(EOL));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AssembleOptions, Assembly};
+
+    #[test]
+    fn test_lexer_reuse() {
+        let mut lexer = Lexer::new();
+        let options = AssembleOptions::default();
+        for src in &[
+            "start: mov al, 'a'\njz start",
+            ".db \"ab\", 1h..3h ; data\n.org 10h",
+            "mov al, @foo\nret",
+            "add al,\\\n [bl]",
+            "",
+        ] {
+            let threaded = Assembly::assemble(src.to_string());
+            let reused = Assembly::assemble_with_lexer(src, &mut lexer, &options);
+            match (threaded, reused) {
+                (Ok(threaded), Ok(reused)) => assert_eq!(threaded, reused),
+                (Err(threaded), Err(reused)) => {
+                    assert_eq!(format!("{:?}", threaded), format!("{:?}", reused))
+                }
+                (threaded, reused) => panic!("{:?} != {:?}", threaded, reused),
+            }
+        }
+    }
+
+    #[test]
+    fn test_directive_help() {
+        let help = TokenizingError::BadDirective.help_msg();
+        for &directive in &Directive::ALL {
+            let name = format!(".{:?}", directive).to_lowercase();
+            let tokens = Assembly::dump_tokens(&name);
+            assert!(matches!(tokens[0].kind, TokenKind::Directive(found) if found == directive));
+            assert!(help.contains(&format!("`{}`", name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_continuation() {
+        for (line, continued) in &[
+            (".db 1h, \\", true),
+            ("mov al,\\  ", true),
+            ("ret ; see C:\\", false),
+            ("mov al, '\\' ; \\", false),
+            ("mov al, '\\", false),
+            (".db \"a;\", \\", true),
+        ] {
+            assert_eq!(is_continued(line), *continued, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_glued_label() {
+        let mut lexer = Lexer::new();
+        let tokens = lexer.scan("start:inc al").unwrap();
+        match tokens.as_slice() {
+            [Ok(start), Ok(inc), ..] => {
+                assert!(matches!(&start.kind, TokenKind::Label(label) if label == "start"));
+                assert_eq!(start.span, 0..6);
+                assert!(matches!(inc.kind, TokenKind::Mnemonic(Mnemonic::Inc)));
+                assert_eq!(inc.span, 6..9);
+            }
+            _ => panic!("the label was not split from the mnemonic"),
+        }
+        for src in &[
+            "start:inc al\njmp start",
+            "start:;comment\ninc al\njmp start",
+        ] {
+            let glued = Assembly::assemble(src.to_string()).unwrap();
+            let spaced = Assembly::assemble("start: inc al\njmp start".into()).unwrap();
+            assert_eq!(glued, spaced);
+        }
+    }
+
+    #[test]
+    fn test_final_token_span() {
+        let mut lexer = Lexer::new();
+        for (src, spans) in &[
+            ("mov al, bl", [0..3, 4..6, 6..7, 8..10]),
+            ("mov al, 'é'", [0..3, 4..6, 6..7, 8..11]),
+            ("\tadd al,5h ; sum", [1..4, 5..7, 7..8, 8..10]),
+        ] {
+            let found: Vec<_> = lexer
+                .scan(src)
+                .unwrap()
+                .iter()
+                .filter_map(|token| token.as_ref().ok().map(|token| token.span.clone()))
+                .collect();
+            assert_eq!(&found, spans, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let src = "start: ret ; the end\n;; banner\n.db 1h;x";
+        let mut lexer = Lexer::new().keep_comments(true);
+        let comments: Vec<_> = lexer
+            .tokenize(src)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Comment(text) => Some((text, token.span, token.line)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                (" the end".to_string(), 11..20, 1),
+                ("; banner".to_string(), 0..9, 2),
+                ("x".to_string(), 6..8, 3),
+            ]
+        );
+        let options = AssembleOptions::default();
+        let assembly = Assembly::assemble_with_lexer(src, &mut lexer, &options).unwrap();
+        assert_eq!(assembly, Assembly::assemble(src.to_string()).unwrap());
+        let src = ".if 1 ; always\nret\n.endif ; done";
+        let assembly = Assembly::assemble_with_lexer(src, &mut lexer, &options).unwrap();
+        assert_eq!(assembly, Assembly::assemble(src.to_string()).unwrap());
+        assert!(Lexer::new()
+            .tokenize(src)
+            .unwrap()
+            .iter()
+            .all(|token| { !matches!(token.kind, TokenKind::Comment(_)) }));
+    }
+
+    #[test]
+    fn test_quoted_literals() {
+        for (src, byte) in &[
+            (r"mov al, '\n'", b'\n'),
+            ("mov al, '\\r'\r\n", b'\r'),
+            (r"mov al, '\''", b'\''),
+            (r#"mov al, "\"""#, b'"'),
+            (r"mov al, '\\'", b'\\'),
+            ("mov al, ';'\r\n", b';'),
+        ] {
+            let assembly = Assembly::assemble(src.to_string()).unwrap();
+            assert_eq!(assembly.as_byte_code().last(), Some(byte), "{}", src);
+        }
+        for (src, span) in &[
+            ("mov al, 'a\r\nret", 8..10),
+            ("mov al, '", 8..9),
+            ("mov al, '\\'\nret", 8..11),
+            (".db 1h, \"ab\r\n", 8..11),
+        ] {
+            let mut lexer = Lexer::new();
+            let err = lexer.tokenize(src).unwrap_err();
+            assert!(
+                matches!(
+                    err.code,
+                    ErrorCode::Token(TokenizingError::UnterminatedLiteral)
+                ),
+                "{}",
+                src
+            );
+            assert_eq!(err.token.span, *span, "{}", src);
+        }
+        let err = Lexer::new().tokenize(r"mov al, '\q'").unwrap_err();
+        assert!(matches!(
+            err.code,
+            ErrorCode::Token(TokenizingError::BadEscape)
+        ));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let err = scan("mov al, @foo bl\nret", sender).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::UnknownToken(_)));
+        let kinds = receiver
+            .iter()
+            .map(|token| token.ok().map(|token| token.kind))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                Some(TokenKind::Mnemonic(Mnemonic::Mov)),
+                Some(TokenKind::Operand(Primitive::Accumulator)),
+                Some(TokenKind::Comma),
+                None,
+                Some(TokenKind::Mnemonic(Mnemonic::Ret)),
+                None,
+            ]
+        ));
     }
-    Ok(())
 }
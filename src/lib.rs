@@ -1,8 +1,24 @@
 mod assembly;
 mod error;
 mod intel;
+mod interp;
 mod lexer;
+mod options;
 mod parser;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-pub use assembly::Assembly;
+pub use assembly::{
+    default_output_path, Assembly, FollowSymlinks, HexCase, LineEnding, LogisimEnding, Mismatch,
+    OutputFormat, Radix, Segment,
+};
 pub use error::*;
+pub use intel::{
+    disassembly::{disassemble, Decoded, DecodedKind},
+    instruction::{DataFlow, Encoding, Instruction, IsaTable},
+    token::{Directive, Mnemonic, Port, Primitive, Register, Token, TokenKind},
+};
+pub use interp::{run, run_with, Halt, MachineState};
+pub use lexer::Lexer;
+pub use options::{AssembleOptions, Gaps, PortMask, Target};
+pub use parser::{ByteCodeBuilder, DetailedOutput, StatementAssembler};
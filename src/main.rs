@@ -1,14 +1,304 @@
-use mpp::Assembly;
+use mpp::{
+    default_output_path, AssembleOptions, Assembly, AssemblyErrors, ErrorCode, Gaps, OutputFormat,
+    Radix, StatementAssembler, Target,
+};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
 
 // TODO: add tests
 // TODO: actually make this a cli
 // TODO: add a gui maybe?
 
-fn main() {
-    let path = std::env::args().nth(1).unwrap();
-    let src = std::fs::read_to_string(&path).unwrap();
-    match Assembly::from_path(&path) {
-        Ok(assembly) => print!("{:?}", assembly),
-        Err(err) => err.throw(&src, &path, None),
+/// Exit code for sources that failed to assemble
+const ASSEMBLY_FAILURE: u8 = 1;
+/// Exit code for files that could not be read or written
+const IO_FAILURE: u8 = 2;
+/// Exit code for arguments that could not be made sense of
+const USAGE_FAILURE: u8 = 3;
+/// How many errors `--all-errors` reports at most
+const DEFAULT_MAX_ERRORS: usize = 20;
+/// How often `--watch` checks the source for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+fn main() -> ExitCode {
+    let mut path = None;
+    let mut dump = false;
+    let mut quiet = false;
+    let mut segments = false;
+    let mut stats = false;
+    let mut output = None;
+    let mut listing = None;
+    let mut rom_out = None;
+    let mut ram_out = None;
+    let mut max_errors = None;
+    let mut watch = false;
+    let mut strip = false;
+    let mut save = false;
+    let mut repl = false;
+    let mut dump_tokens = false;
+    let mut target = None;
+    let mut base = None;
+    let mut defines = HashMap::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Assemble statements typed one by one instead of a file
+            "repl" if path.is_none() && !repl => repl = true,
+            "--dump" => dump = true,
+            // Print how the source was tokenized instead of assembling it
+            "--dump-tokens" => dump_tokens = true,
+            "--quiet" => quiet = true,
+            "--segments" => segments = true,
+            "--stats" => stats = true,
+            "--watch" => watch = true,
+            "--strip" => strip = true,
+            // Write the byte code next to the source unless `-o` says where
+            "--save" => save = true,
+            "-o" => match flag_value(&arg, args.next(), str::parse::<String>) {
+                Ok(file) => output = Some(file),
+                Err(msg) => return usage_failure(msg),
+            },
+            "--listing-to" => match flag_value(&arg, args.next(), str::parse::<String>) {
+                Ok(file) => listing = Some(file),
+                Err(msg) => return usage_failure(msg),
+            },
+            "--rom-out" => match flag_value(&arg, args.next(), str::parse::<String>) {
+                Ok(file) => rom_out = Some(file),
+                Err(msg) => return usage_failure(msg),
+            },
+            "--ram-out" => match flag_value(&arg, args.next(), str::parse::<String>) {
+                Ok(file) => ram_out = Some(file),
+                Err(msg) => return usage_failure(msg),
+            },
+            // Report every error rather than the first, up to a cap
+            "--all-errors" => max_errors = max_errors.or(Some(DEFAULT_MAX_ERRORS)),
+            "--max-errors" => match flag_value(&arg, args.next(), str::parse) {
+                Ok(max) => max_errors = Some(max),
+                Err(msg) => return usage_failure(msg),
+            },
+            "--target" => match flag_value(&arg, args.next(), str::parse::<Target>) {
+                Ok(preset) => target = Some(preset),
+                Err(msg) => return usage_failure(msg),
+            },
+            // --base 0x200 or --base 512
+            "--base" => {
+                let parse = |base: &str| match base.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16),
+                    None => base.parse(),
+                };
+                match flag_value(&arg, args.next(), parse) {
+                    Ok(address) => base = Some(address),
+                    Err(msg) => return usage_failure(msg),
+                }
+            }
+            // -DNAME or -DNAME=VALUE
+            _ if arg.starts_with("-D") => {
+                let (name, value) = match arg[2..].split_once('=') {
+                    Some((name, value)) => match value.parse() {
+                        Ok(value) => (name, value),
+                        Err(err) => return usage_failure(format!("-D{}: {}", name, err)),
+                    },
+                    None => (&arg[2..], 1),
+                };
+                defines.insert(name.into(), value);
+            }
+            _ if arg.starts_with('-') => return usage_failure(format!("unknown flag {}", arg)),
+            _ if path.is_some() => {
+                return usage_failure(format!("more than one source file given: {}", arg))
+            }
+            _ => path = Some(arg),
+        }
+    }
+    // The granular flags adjust the target's options wherever they were given
+    let mut options = target.map_or_else(AssembleOptions::default, Target::options);
+    if let Some(base) = base {
+        options.base_address = base;
+    }
+    options.defines.extend(defines);
+    if repl {
+        return run_repl(&options);
+    }
+    let path = match path {
+        Some(path) => path,
+        None => return usage_failure("no source file given"),
+    };
+    if save && output.is_none() {
+        let default = default_output_path(Path::new(&path), OutputFormat::Binary);
+        output = Some(default.to_string_lossy().into_owned());
     }
+    let run = || {
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(err) => return io_failure(&path, err),
+        };
+        if dump_tokens {
+            for token in Assembly::dump_tokens(&src) {
+                let span = token.span;
+                println!(
+                    "{}:{}..{} {:?}",
+                    token.line, span.start, span.end, token.kind
+                );
+            }
+            return ExitCode::SUCCESS;
+        }
+        let assembled = match max_errors {
+            Some(max_errors) => Assembly::assemble_collecting_errors(&src, &options, max_errors),
+            None => Assembly::assemble_with(src.clone(), &options).map_err(AssemblyErrors::from),
+        };
+        match assembled {
+            Ok(mut assembly) => {
+                if strip {
+                    // Whatever `.org` fills gaps with is what pads the image
+                    let fill = match options.gaps {
+                        Gaps::Fill(byte) => byte,
+                        Gaps::Sparse => 0,
+                    };
+                    assembly.strip_trailing(fill);
+                }
+                for warning in assembly.warnings() {
+                    warning.throw(&src, &path, None);
+                }
+                if let Some(file) = &listing {
+                    if let Err(err) = fs::write(file, assembly.to_listing(&src, Radix::default())) {
+                        return io_failure(file, err);
+                    }
+                }
+                if let Some(file) = &output {
+                    if let Err(err) = fs::write(file, assembly.as_byte_code()) {
+                        return io_failure(file, err);
+                    }
+                }
+                let (rom, ram) = assembly.split_rom_ram();
+                for (file, image) in [(&rom_out, rom), (&ram_out, ram)] {
+                    if let Some(file) = file {
+                        if let Err(err) = fs::write(file, image) {
+                            return io_failure(file, err);
+                        }
+                    }
+                }
+                if segments {
+                    for segment in assembly.segments() {
+                        let end = segment.start as usize + segment.len;
+                        println!("{:04x}..{:04x} {} bytes", segment.start, end, segment.len);
+                    }
+                }
+                if stats {
+                    println!(
+                        "{} bytes, {} decoder page turns",
+                        assembly.as_byte_code().len(),
+                        assembly.page_turn_count()
+                    );
+                }
+                if dump {
+                    print!("{}", assembly.hexdump());
+                } else if !quiet {
+                    print!("{:?}", assembly);
+                }
+                ExitCode::SUCCESS
+            }
+            Err(errors) => {
+                for err in &errors.errors {
+                    err.throw(&src, &path, None);
+                }
+                if errors.overflow > 0 {
+                    eprintln!(
+                        "… and {} more error{}",
+                        errors.overflow,
+                        if errors.overflow == 1 { "" } else { "s" }
+                    );
+                }
+                match errors.errors.first().map(|err| &err.code) {
+                    Some(ErrorCode::Io(_)) => ExitCode::from(IO_FAILURE),
+                    _ => ExitCode::from(ASSEMBLY_FAILURE),
+                }
+            }
+        }
+    };
+    if !watch {
+        return run();
+    }
+    // Poll the source for changes, assembling it anew each time
+    let mut last_modified = None;
+    loop {
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+        if modified != last_modified {
+            last_modified = modified;
+            eprintln!("-- assembling {}", path);
+            run();
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Reads statements from the standard input, printing the bytes each one
+/// assembles into. `.bytes` prints the whole program so far, and `.sym` its
+/// labels.
+fn run_repl(options: &AssembleOptions) -> ExitCode {
+    let mut assembler = StatementAssembler::new(options);
+    // Every statement fed so far, for errors to point into
+    let mut history = String::new();
+    let mut input = String::new();
+    let hex = |bytes: &[u8]| {
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        bytes.join(" ")
+    };
+    loop {
+        print!("> ");
+        io::stdout().flush().ok();
+        input.clear();
+        match io::stdin().read_line(&mut input) {
+            // End of input, which leaves the prompt on its own line
+            Ok(0) => {
+                println!();
+                return ExitCode::SUCCESS;
+            }
+            Ok(_) => (),
+            Err(err) => return io_failure("stdin", err),
+        }
+        let line = input.trim_end();
+        match line.trim() {
+            ".bytes" => println!("{}", hex(&assembler.bytes())),
+            ".sym" => {
+                for (label, address) in assembler.symbols() {
+                    println!("{:04x} {}", address, label);
+                }
+            }
+            _ => {
+                history.push_str(line);
+                history.push('\n');
+                match assembler.feed(line) {
+                    Ok(bytes) if bytes.is_empty() => (),
+                    Ok(bytes) => println!("{}", hex(&bytes)),
+                    Err(err) => err.throw(&history, &"stdin", None),
+                }
+            }
+        }
+    }
+}
+
+/// Parses the value given to `flag`, should there be one.
+fn flag_value<T, E: fmt::Display>(
+    flag: &str,
+    value: Option<String>,
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} expects a value", flag))?;
+    parse(&value).map_err(|err| format!("{} {}: {}", flag, value, err))
+}
+
+fn usage_failure(msg: impl fmt::Display) -> ExitCode {
+    eprintln!("usage error: {}", msg);
+    ExitCode::from(USAGE_FAILURE)
+}
+
+fn io_failure(file: &str, err: io::Error) -> ExitCode {
+    eprintln!("{}: {}", file, err);
+    ExitCode::from(IO_FAILURE)
 }
@@ -0,0 +1,227 @@
+use crate::{HexCase, IsaTable, LineEnding, LogisimEnding, Port};
+use std::{collections::HashMap, str::FromStr};
+
+/// What is done with the bytes skipped over by `.org`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gaps {
+    /// Fill them with the given byte, making them part of the image
+    Fill(u8),
+    /// Leave them out of the image, for formats that support gaps. They are
+    /// still zeroed in the byte code, but reported by
+    /// [`Assembly::gaps`](crate::Assembly::gaps).
+    Sparse,
+}
+
+impl Default for Gaps {
+    fn default() -> Self {
+        Self::Fill(0)
+    }
+}
+
+/// The I/O ports a target has, as bit masks where bit `n` stands for port `n`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PortMask {
+    pub inputs: u8,
+    pub outputs: u8,
+}
+
+impl PortMask {
+    /// Whether `port` is among the available ones.
+    pub fn contains(self, port: Port) -> bool {
+        match port {
+            Port::Input(num) => self.inputs & (1 << num) != 0,
+            Port::Output(num) => self.outputs & (1 << num) != 0,
+        }
+    }
+}
+
+/// Named processor variants, each standing for a set of [`AssembleOptions`] so
+/// that a whole target is picked at once. The options returned may still be
+/// adjusted one by one afterwards.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Target {
+    /// The processor as documented, with a single input and output port
+    Base,
+    /// The processor with all four input and output ports wired up
+    ExtendedIo,
+}
+
+impl Target {
+    pub const ALL: [Self; 2] = [Self::Base, Self::ExtendedIo];
+
+    /// The name the target goes by on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Base => "base",
+            Self::ExtendedIo => "extended-io",
+        }
+    }
+
+    pub fn options(self) -> AssembleOptions {
+        // Stack operations and dynamic memory live on the last page
+        let options = AssembleOptions::new().max_decoder_page(2);
+        match self {
+            Self::Base => options.available_ports(PortMask {
+                inputs: 0b0001,
+                outputs: 0b0001,
+            }),
+            Self::ExtendedIo => options.available_ports(PortMask {
+                inputs: 0b1111,
+                outputs: 0b1111,
+            }),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|target| target.name().eq_ignore_ascii_case(src))
+            .ok_or_else(|| format!("unknown target `{}`", src))
+    }
+}
+
+/// Settings that change how a source is assembled.
+#[derive(Clone, Debug, Default)]
+pub struct AssembleOptions {
+    /// The highest decoder page the target processor is able to turn to.
+    /// Instructions living beyond it are rejected instead of being emitted
+    /// with page turns the target cannot interpret. `None` means unlimited.
+    pub max_decoder_page: Option<usize>,
+    /// The encodings of every mnemonic and data flow
+    pub isa: IsaTable,
+    /// Whether pushes and pops are checked to balance out before every `ret`.
+    /// The check follows the source linearly, so it is only a heuristic.
+    pub check_stack_balance: bool,
+    /// The amount of values the target's stack is able to hold. When set,
+    /// pushes that would go beyond it are warned about, following the source
+    /// just as the stack balance check does.
+    pub stack_size: Option<usize>,
+    /// Whether warnings are returned as errors instead, all of them when
+    /// assembling with
+    /// [`assemble_collecting_errors`](crate::Assembly::assemble_collecting_errors),
+    /// or else the first one in the source.
+    pub warnings_as_errors: bool,
+    /// What to do with the bytes skipped over by `.org`
+    pub gaps: Gaps,
+    /// Whether statements the processor cannot execute directly are expanded
+    /// into several instructions that go through the accumulator, such as
+    /// `mov out0, 1h` into `mov al, 1h` and `mov out0, al`, or `mov bl, cl`
+    /// into `mov al, cl` and `mov bl, al`. The accumulator is clobbered, so a
+    /// warning is given for each.
+    pub pseudo_instructions: bool,
+    /// Whether expanding a pseudo instruction is an error rather than a
+    /// warning, since it clobbers the accumulator behind the source's back.
+    pub forbid_implicit_clobber: bool,
+    /// The values of the names `.if` blocks may test, such as `DEBUG` in
+    /// `.if DEBUG`. Names are case insensitive, and undefined ones count as
+    /// `0`.
+    pub defines: HashMap<String, u8>,
+    /// The ports the target has. Using any other one is an error. `None`
+    /// means every port is available.
+    pub available_ports: Option<PortMask>,
+    /// The case of hexadecimal digits in the text outputs of the assembly
+    pub hex_case: HexCase,
+    /// What follows the last byte of a Logisim memory image
+    pub logisim_ending: LogisimEnding,
+    /// The line break in the text outputs of the assembly. `None` keeps the
+    /// one of each format: `\r\n` for Logisim images, and `\n` otherwise.
+    pub line_ending: Option<LineEnding>,
+    /// The address the program is placed at, which is added to every label
+    /// and to `$`, though not to the addresses given to `.org`, which count
+    /// from the start of the program. Simpler than `.org` for programs made
+    /// of a single segment.
+    pub base_address: u16,
+    /// Whether `acc` and `a` are taken as the accumulator, as some tutorials
+    /// call it, rather than as labels. Constants of the same name still take
+    /// precedence.
+    pub accumulator_aliases: bool,
+}
+
+/// Every setting may also be chained onto the defaults, as in
+/// `AssembleOptions::new().max_decoder_page(1).pseudo_instructions(true)`.
+impl AssembleOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_decoder_page(mut self, page: usize) -> Self {
+        self.max_decoder_page = Some(page);
+        self
+    }
+
+    pub fn isa(mut self, isa: IsaTable) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    pub fn check_stack_balance(mut self, check: bool) -> Self {
+        self.check_stack_balance = check;
+        self
+    }
+
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    pub fn warnings_as_errors(mut self, deny: bool) -> Self {
+        self.warnings_as_errors = deny;
+        self
+    }
+
+    pub fn gaps(mut self, gaps: Gaps) -> Self {
+        self.gaps = gaps;
+        self
+    }
+
+    pub fn pseudo_instructions(mut self, expand: bool) -> Self {
+        self.pseudo_instructions = expand;
+        self
+    }
+
+    pub fn forbid_implicit_clobber(mut self, forbid: bool) -> Self {
+        self.forbid_implicit_clobber = forbid;
+        self
+    }
+
+    /// Defines `name` as `value` for `.if` blocks to test.
+    pub fn define(mut self, name: &str, value: u8) -> Self {
+        self.defines.insert(name.into(), value);
+        self
+    }
+
+    pub fn available_ports(mut self, ports: PortMask) -> Self {
+        self.available_ports = Some(ports);
+        self
+    }
+
+    pub fn hex_case(mut self, case: HexCase) -> Self {
+        self.hex_case = case;
+        self
+    }
+
+    pub fn logisim_ending(mut self, ending: LogisimEnding) -> Self {
+        self.logisim_ending = ending;
+        self
+    }
+
+    pub fn line_ending(mut self, ending: LineEnding) -> Self {
+        self.line_ending = Some(ending);
+        self
+    }
+
+    pub fn base_address(mut self, base: u16) -> Self {
+        self.base_address = base;
+        self
+    }
+
+    pub fn accumulator_aliases(mut self, allow: bool) -> Self {
+        self.accumulator_aliases = allow;
+        self
+    }
+}
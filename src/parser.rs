@@ -1,138 +1,1564 @@
 use crate::{
     intel::{
+        disassembly::{self, DecodedKind},
+        expression,
         instruction::Instruction,
         token::{self, *},
     },
-    AssemblyError, ErrorCode,
+    lexer, AssembleOptions, Assembly, AssemblyError, AssemblyWarning, ErrorCode, Gaps, Segment,
+    WarningCode,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
 };
-use std::{collections::HashMap, sync::mpsc::Receiver};
 
 enum ByteCode {
     Byte(u8),
     Addr((Token, String)),
+    /// The address of the numeric label with the given number, defined the
+    /// given amount of times before it
+    LocalAddr((Token, u32, usize)),
+    /// An address already known, which is still relocated as label addresses
+    /// are
+    KnownAddr(u16),
+    /// Bytes skipped over by `.org`
+    Gap((Token, u16)),
+}
+
+impl ByteCode {
+    /// The amount of bytes this will take once the addresses are filled
+    fn len(&self) -> u16 {
+        match self {
+            Self::Byte(_) => 1,
+            Self::Addr(_) | Self::LocalAddr(_) | Self::KnownAddr(_) => 2,
+            Self::Gap((_, len)) => *len,
+        }
+    }
+}
+
+/// Everything the parser learns about a program.
+#[derive(Debug)]
+pub(crate) struct Output {
+    pub data: Vec<u8>,
+    /// The source line and the addresses of the bytes of every statement that
+    /// emitted any
+    pub source_map: Vec<(usize, Range<u16>)>,
+    pub labels: HashMap<String, u16>,
+    pub warnings: Vec<AssemblyWarning>,
+    /// The addresses skipped over by `.org`, if they were left sparse
+    pub gaps: Vec<Range<u16>>,
+    /// The offsets of every label address written into `data`
+    pub relocations: Vec<usize>,
+    /// The runs of bytes in between `.org` gaps
+    pub segments: Vec<Segment>,
+    /// The addresses of every instruction, as opposed to data
+    pub code: Vec<Range<u16>>,
+    /// Every instruction emitted, in order, as it was built
+    pub instructions: Vec<Instruction>,
+}
+
+impl Output {
+    /// Takes every warning out as the error it is promoted to, in the order of
+    /// their lines, as long as `options` takes warnings as errors.
+    pub(crate) fn promote_warnings(&mut self, options: &AssembleOptions) -> Vec<AssemblyError> {
+        if !options.warnings_as_errors {
+            return Vec::new();
+        }
+        let mut errors: Vec<_> = self
+            .warnings
+            .drain(..)
+            .map(|warning| AssemblyError::new(warning.token, ErrorCode::Warning(warning.code)))
+            .collect();
+        errors.sort_by_key(|err| err.token.line);
+        errors
+    }
 }
 
-pub fn eval(receiver: Receiver<Result<Token, EOL>>) -> Result<Vec<u8>, AssemblyError> {
-    let mut byte_code = Vec::<ByteCode>::new();
+pub fn eval(
+    tokens: impl IntoIterator<Item = Result<Token, EOL>>,
+    options: &AssembleOptions,
+) -> Result<Output, AssemblyError> {
+    eval_with_progress(tokens, options, |_, _| ())
+}
+
+/// The bytes, the instructions as they were built, and the labels' addresses,
+/// as handed back by [`eval_detailed`].
+pub type DetailedOutput = (Vec<u8>, Vec<Instruction>, HashMap<String, u16>);
+
+/// Same as [`eval`], but hands back the bytes along with every instruction as
+/// it was built, jump targets included, and the address of every label.
+/// Warnings are left out, unless they are to be taken as errors.
+pub fn eval_detailed(
+    tokens: impl IntoIterator<Item = Result<Token, EOL>>,
+    options: &AssembleOptions,
+) -> Result<DetailedOutput, AssemblyError> {
+    let mut output = eval(tokens, options)?;
+    if let Some(err) = output.promote_warnings(options).into_iter().next() {
+        return Err(err);
+    }
+    Ok((output.data, output.instructions, output.labels))
+}
+
+/// Same as [`eval`], but calls `progress` at the end of every line with its
+/// number and the amount of bytes emitted so far.
+pub fn eval_with_progress(
+    tokens: impl IntoIterator<Item = Result<Token, EOL>>,
+    options: &AssembleOptions,
+    progress: impl FnMut(usize, usize),
+) -> Result<Output, AssemblyError> {
+    translate(tokens, options, progress)?.finish()
+}
+
+/// Same as [`eval`], but writes the bytes after those already in `data`
+/// instead of into [`Output::data`], which is left empty.
+pub fn eval_into(
+    tokens: impl IntoIterator<Item = Result<Token, EOL>>,
+    options: &AssembleOptions,
+    data: &mut Vec<u8>,
+) -> Result<Output, AssemblyError> {
+    translate(tokens, options, |_, _| ())?.finish_into(data)
+}
+
+fn translate<'a>(
+    tokens: impl IntoIterator<Item = Result<Token, EOL>>,
+    options: &'a AssembleOptions,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<Parser<'a>, AssemblyError> {
+    let mut parser = Parser::new(options);
     let mut buffer = Vec::new();
-    let mut labels_idx = HashMap::new();
-    for maybe_token in receiver.iter() {
+    let mut line = 0;
+    for maybe_token in tokens {
         match maybe_token {
             Ok(token) => buffer.push(token),
-            Err(_) => translate_buffer(&mut buffer, &mut byte_code, &mut labels_idx)?,
+            Err(_) => {
+                // Empty lines carry no tokens to take the number from
+                line = buffer.last().map_or(line + 1, |token: &Token| token.line);
+                parser.translate_line(&mut buffer)?;
+                progress(line, parser.address as usize);
+            }
+        }
+    }
+    Ok(parser)
+}
+
+/// Same as [`eval`], but carries on with the next line after an error instead
+/// of stopping, collecting every error into `errors`. There is no output if
+/// any was found.
+pub fn eval_recovering(
+    tokens: impl IntoIterator<Item = Result<Token, EOL>>,
+    options: &AssembleOptions,
+    errors: &mut Vec<AssemblyError>,
+) -> Option<Output> {
+    let mut parser = Parser::new(options);
+    let mut buffer = Vec::new();
+    for maybe_token in tokens {
+        match maybe_token {
+            Ok(token) => buffer.push(token),
+            Err(_) => {
+                if let Err(err) = parser.translate_line(&mut buffer) {
+                    errors.push(err);
+                    buffer.clear();
+                }
+            }
+        }
+    }
+    match parser.finish() {
+        Ok(output) if errors.is_empty() => Some(output),
+        Ok(_) => None,
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    }
+}
+
+/// Assembles a source one line at a time, as typed into a monitor, handing
+/// back the bytes of each line right away.
+pub struct StatementAssembler<'a> {
+    parser: Parser<'a>,
+    line: usize,
+}
+
+impl<'a> StatementAssembler<'a> {
+    pub fn new(options: &'a AssembleOptions) -> Self {
+        Self {
+            parser: Parser::new(options),
+            line: 0,
+        }
+    }
+
+    /// Assembles a single line of source, returning the bytes it was
+    /// translated into. Addresses of labels not yet defined are left as `0`
+    /// until [`finalize`](Self::finalize) resolves them.
+    pub fn feed(&mut self, line: &str) -> Result<Vec<u8>, AssemblyError> {
+        self.line += 1;
+        let line_no = self.line;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        // Tokens are numbered as if every line fed so far were a single source
+        lexer::scan(line, sender).map_err(|mut err| {
+            err.token.line = line_no;
+            err
+        })?;
+        let mut buffer = receiver
+            .try_iter()
+            .filter_map(Result::ok)
+            .map(|token| Token {
+                line: line_no,
+                ..token
+            })
+            .collect();
+        let start = self.parser.byte_code.len();
+        let checkpoint = self.parser.checkpoint();
+        if let Err(err) = self.parser.translate_line(&mut buffer) {
+            // The line may be fed again once fixed, so nothing of it is kept
+            self.parser.rollback(checkpoint, line_no);
+            return Err(err);
+        }
+        Ok(self.known_bytes(&self.parser.byte_code[start..]))
+    }
+
+    /// Every byte fed so far, with the addresses of labels not yet defined
+    /// left as `0`.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.known_bytes(&self.parser.byte_code)
+    }
+
+    /// Every label defined so far and its address, ordered as in
+    /// [`Assembly::symbols`].
+    pub fn symbols(&self) -> Vec<(&str, u16)> {
+        let mut symbols: Vec<_> = self
+            .parser
+            .labels_idx
+            .iter()
+            .map(|(label, (address, _))| (label.as_str(), *address))
+            .collect();
+        symbols.sort_unstable_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        symbols
+    }
+
+    fn known_bytes(&self, byte_code: &[ByteCode]) -> Vec<u8> {
+        let fill = match self.parser.options.gaps {
+            Gaps::Fill(byte) => byte,
+            Gaps::Sparse => 0,
+        };
+        let mut bytes = Vec::new();
+        for byte_code in byte_code {
+            match byte_code {
+                ByteCode::Byte(byte) => bytes.push(*byte),
+                ByteCode::Addr((_, label)) => {
+                    let address = self
+                        .parser
+                        .labels_idx
+                        .get(label)
+                        .map(|(address, _)| *address);
+                    bytes.extend_from_slice(&address.unwrap_or(0).to_be_bytes());
+                }
+                ByteCode::LocalAddr((_, number, nth)) => {
+                    let address = self.parser.local_address(*number, *nth);
+                    bytes.extend_from_slice(&address.unwrap_or(0).to_be_bytes());
+                }
+                ByteCode::KnownAddr(address) => bytes.extend_from_slice(&address.to_be_bytes()),
+                ByteCode::Gap((_, len)) => bytes.resize(bytes.len() + *len as usize, fill),
+            }
         }
+        bytes
     }
-    fill_addresses(byte_code, &labels_idx)
+
+    /// Resolves every label address, returning the whole byte code.
+    pub fn finalize(self) -> Result<Vec<u8>, AssemblyError> {
+        self.parser.finish().map(|output| output.data)
+    }
+}
+
+/// Builds byte code from instructions, data, and label addresses handed over
+/// one by one, for code generators that have no source to assemble. The
+/// tokens of its errors are numbered by call, as if each were a line.
+pub struct ByteCodeBuilder<'a> {
+    parser: Parser<'a>,
+    calls: usize,
+}
+
+impl<'a> ByteCodeBuilder<'a> {
+    pub fn new(options: &'a AssembleOptions) -> Self {
+        Self {
+            parser: Parser::new(options),
+            calls: 0,
+        }
+    }
+
+    fn token(&mut self, kind: TokenKind) -> Token {
+        self.calls += 1;
+        Token {
+            kind,
+            span: 0..0,
+            line: self.calls,
+        }
+    }
+
+    /// Places `name` at the address the next byte will be emitted at.
+    pub fn label(&mut self, name: &str) -> Result<&mut Self, AssemblyError> {
+        let token = self.token(TokenKind::Label(name.into()));
+        self.parser.define_label(&token, name)?;
+        Ok(self)
+    }
+
+    /// Emits an instruction, as long as the target supports it.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`Instruction::as_bytes`] apply.
+    pub unsafe fn emit_instruction(
+        &mut self,
+        inst: Instruction,
+    ) -> Result<&mut Self, AssemblyError> {
+        let token = self.token(TokenKind::Error);
+        let start = self.parser.address;
+        self.parser.emit_instruction(inst, &token)?;
+        self.parser
+            .instructions
+            .push((token, start..self.parser.address));
+        Ok(self)
+    }
+
+    /// Emits raw data.
+    pub fn emit_bytes(&mut self, bytes: &[u8]) -> Result<&mut Self, AssemblyError> {
+        let token = self.token(TokenKind::Error);
+        self.parser.emit_bytes(bytes.iter().copied(), &token)?;
+        Ok(self)
+    }
+
+    /// Emits the two byte address of `label`, which may be defined later on,
+    /// as jumps and `.jmptable` do.
+    pub fn emit_address(&mut self, label: &str) -> Result<&mut Self, AssemblyError> {
+        let token = self.token(TokenKind::Label(label.into()));
+        let addr = ByteCode::Addr((token.clone(), label.into()));
+        self.parser.emit(addr, &token)?;
+        Ok(self)
+    }
+
+    /// Resolves every label address, handing back the whole assembly.
+    pub fn finish(self) -> Result<Assembly, AssemblyError> {
+        let options = self.parser.options;
+        Assembly::from_output(self.parser.finish()?, options)
+    }
+}
+
+/// Other names for `al`, accepted with
+/// [`AssembleOptions::accumulator_aliases`].
+fn is_accumulator_alias(name: &str) -> bool {
+    name == "acc" || name == "a"
 }
 
 macro_rules! throw {
     ($token:expr, $code:ident$(($($qty:ident),+))?) => {
-        return Result::Err(AssemblyError::new($token, ErrorCode::$code$(($($qty),+))?));
+        return Result::Err(AssemblyError::new($token, ErrorCode::$code$(($($qty),+))?))
     };
 }
 
-fn translate_buffer(
-    buffer: &mut Vec<Token>,
-    byte_code: &mut Vec<ByteCode>,
-    labels_idx: &mut HashMap<String, u16>,
-) -> Result<(), AssemblyError> {
-    use TokenKind::*;
-    // Inside the buffer we have a line of mpp assembly tokens,
-    // which is structured as:
-    // [label] [mnemonic [operands]]
-    let mut operands_req = 0;
-    let mut operands_found = 0;
-    let mut operands: [Option<(Token, Primitive)>; 2] = [None, None];
-    let mut stmt_mnemonic: Option<(Token, token::Mnemonic)> = None;
-    for token in buffer.drain(..) {
-        match &token.kind {
-            Label(label) => {
-                if labels_idx
-                    .insert(label.clone(), byte_code.len() as u16)
-                    .is_some()
-                {
-                    throw!(token, RedefinedLabel);
-                }
-            }
-            Mnemonic(mnemonic) => {
-                if stmt_mnemonic.is_some() {
-                    throw!(token, MultipleMnemonics);
-                }
-                operands_req = mnemonic.operands_required();
-                stmt_mnemonic = Some((token.clone(), *mnemonic));
-            }
-            Operand(primitive) => {
-                operands[operands_found].replace((token.clone(), primitive.clone()));
-                operands_found += 1;
-            }
-            Comma => match stmt_mnemonic {
-                None => throw!(token, NoMnemonic),
-                Some((ref mnemonic_token, _)) => {
-                    if operands_req == operands_found {
-                        throw!(mnemonic_token.clone(), ExcessiveOperands(operands_req));
-                    } else if operands_found == 0 {
-                        throw!(token.clone(), UnexpectedComma);
+/// A `.if` block being assembled.
+#[derive(Clone)]
+struct Conditional {
+    /// The `.if` that started the block
+    token: Token,
+    /// Whether the lines of the current branch are kept
+    active: bool,
+    /// Whether the block itself is within a kept branch
+    parent_active: bool,
+    /// Whether `.else` was already found
+    in_else: bool,
+}
+
+struct Parser<'a> {
+    options: &'a AssembleOptions,
+    byte_code: Vec<ByteCode>,
+    /// The address of every label, along with the token that defined it
+    labels_idx: HashMap<String, (u16, Token)>,
+    /// The addresses of every numeric label, as in `1:`, in the order they
+    /// were defined
+    local_labels: HashMap<u32, Vec<u16>>,
+    source_map: Vec<(usize, Range<u16>)>,
+    /// The address the next emitted byte will be placed at
+    address: u16,
+    /// How many values have been pushed to the stack in the current routine
+    stack_depth: isize,
+    warnings: Vec<AssemblyWarning>,
+    /// The `.if` blocks the current line is in, from the outermost inwards
+    conditionals: Vec<Conditional>,
+    /// The values defined through `.equ`
+    constants: HashMap<String, (u8, Token)>,
+    /// The last instruction emitted, as long as execution may run past it
+    falls_through: Option<Token>,
+    /// Whether the `C` and `Z` flags were set since the last label, or `None`
+    /// where other paths may lead
+    flags_set: Option<(bool, bool)>,
+    /// The address given to the last `.org`
+    last_origin: Option<u16>,
+    /// The mnemonic and the addresses of every instruction statement
+    instructions: Vec<(Token, Range<u16>)>,
+    /// Every instruction emitted, with its address and whether it is a jump
+    /// whose target comes right after it
+    built: Vec<(u16, Instruction, bool)>,
+}
+
+/// The state of a [`Parser`] in between lines, for undoing a line that failed
+/// partway through. Labels are undone by the line that defined them.
+struct Checkpoint {
+    byte_code: usize,
+    source_map: usize,
+    warnings: usize,
+    instructions: usize,
+    built: usize,
+    address: u16,
+    stack_depth: isize,
+    local_labels: HashMap<u32, Vec<u16>>,
+    conditionals: Vec<Conditional>,
+    constants: HashMap<String, (u8, Token)>,
+    falls_through: Option<Token>,
+    flags_set: Option<(bool, bool)>,
+    last_origin: Option<u16>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(options: &'a AssembleOptions) -> Self {
+        Self {
+            options,
+            byte_code: Vec::new(),
+            labels_idx: HashMap::new(),
+            local_labels: HashMap::new(),
+            source_map: Vec::new(),
+            address: 0,
+            stack_depth: 0,
+            warnings: Vec::new(),
+            conditionals: Vec::new(),
+            constants: HashMap::new(),
+            falls_through: None,
+            flags_set: Some((false, false)),
+            last_origin: None,
+            instructions: Vec::new(),
+            built: Vec::new(),
+        }
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            byte_code: self.byte_code.len(),
+            source_map: self.source_map.len(),
+            warnings: self.warnings.len(),
+            instructions: self.instructions.len(),
+            built: self.built.len(),
+            address: self.address,
+            stack_depth: self.stack_depth,
+            local_labels: self.local_labels.clone(),
+            conditionals: self.conditionals.clone(),
+            constants: self.constants.clone(),
+            falls_through: self.falls_through.clone(),
+            flags_set: self.flags_set,
+            last_origin: self.last_origin,
+        }
+    }
+
+    /// Goes back to `checkpoint`, dropping the labels defined on `line`.
+    fn rollback(&mut self, checkpoint: Checkpoint, line: usize) {
+        self.byte_code.truncate(checkpoint.byte_code);
+        self.source_map.truncate(checkpoint.source_map);
+        self.warnings.truncate(checkpoint.warnings);
+        self.instructions.truncate(checkpoint.instructions);
+        self.built.truncate(checkpoint.built);
+        self.address = checkpoint.address;
+        self.stack_depth = checkpoint.stack_depth;
+        self.local_labels = checkpoint.local_labels;
+        self.conditionals = checkpoint.conditionals;
+        self.constants = checkpoint.constants;
+        self.falls_through = checkpoint.falls_through;
+        self.flags_set = checkpoint.flags_set;
+        self.last_origin = checkpoint.last_origin;
+        self.labels_idx.retain(|_, (_, token)| token.line != line);
+    }
+
+    /// Emits `byte_code` at the current address, as long as it fits before
+    /// the end of the address space.
+    fn emit(&mut self, byte_code: ByteCode, token: &Token) -> Result<(), AssemblyError> {
+        let base = self.options.base_address;
+        self.address = match self.address.checked_add(byte_code.len()) {
+            Some(address) => address,
+            None => throw!(token.clone(), AddressOverflow(base)),
+        };
+        self.byte_code.push(byte_code);
+        Ok(())
+    }
+
+    fn emit_bytes(
+        &mut self,
+        bytes: impl IntoIterator<Item = u8>,
+        token: &Token,
+    ) -> Result<(), AssemblyError> {
+        for byte in bytes {
+            self.emit(ByteCode::Byte(byte), token)?;
+        }
+        Ok(())
+    }
+
+    /// Emits a fully encoded instruction, as long as the target supports it.
+    fn emit_instruction(&mut self, inst: Instruction, token: &Token) -> Result<(), AssemblyError> {
+        let max_page = self.options.max_decoder_page.unwrap_or(usize::MAX);
+        let bytes = unsafe { inst.try_as_bytes(max_page) }
+            .map_err(|code| AssemblyError::new(token.clone(), code))?;
+        self.built.push((self.address, inst, false));
+        self.emit_bytes(bytes, token)
+    }
+
+    /// Emits a jump, whose target is to be emitted right after it.
+    fn emit_jump(&mut self, inst: Instruction, token: &Token) -> Result<(), AssemblyError> {
+        self.emit_instruction(inst, token)?;
+        if let Some((_, _, awaits_target)) = self.built.last_mut() {
+            *awaits_target = true;
+        }
+        Ok(())
+    }
+
+    /// Places `label` at the current address.
+    fn define_label(&mut self, token: &Token, label: &str) -> Result<(), AssemblyError> {
+        let base = self.options.base_address;
+        let address = match base.checked_add(self.address) {
+            Some(address) => address,
+            None => throw!(token.clone(), AddressOverflow(base)),
+        };
+        self.flags_set = None;
+        // Numeric labels may be defined any amount of times
+        if Primitive::is_local_label(label) {
+            let number = match label.parse() {
+                Ok(number) => number,
+                Err(_) => {
+                    let err = TokenizingError::BadLabel;
+                    throw!(token.clone(), Token(err))
+                }
+            };
+            self.local_labels.entry(number).or_default().push(address);
+            return Ok(());
+        }
+        // Reserved words make for confusing labels
+        if label.parse::<token::Mnemonic>().is_ok()
+            || label.parse::<Register>().is_ok()
+            || label.parse::<Port>().is_ok()
+            || label == "al"
+            || (self.options.accumulator_aliases && is_accumulator_alias(label))
+        {
+            self.warnings.push(AssemblyWarning::new(
+                token.clone(),
+                WarningCode::ShadowingLabel,
+            ));
+        }
+        if let Some((_, first)) = self.labels_idx.get(label) {
+            let line = first.line;
+            throw!(token.clone(), RedefinedLabel(line));
+        }
+        self.labels_idx
+            .insert(label.into(), (address, token.clone()));
+        Ok(())
+    }
+
+    /// The address of the numeric label `number` defined `nth` in order.
+    fn local_address(&self, number: u32, nth: usize) -> Option<u16> {
+        self.local_labels.get(&number)?.get(nth).copied()
+    }
+
+    /// Emits the address of the nearest numeric label `number` before the
+    /// current statement, or after it if `forward`. Those after it are only
+    /// known once the whole source is.
+    fn emit_local_addr(
+        &mut self,
+        token: Token,
+        number: u32,
+        forward: bool,
+    ) -> Result<(), AssemblyError> {
+        let defined = self.local_labels.get(&number).map_or(0, Vec::len);
+        let nth = match (forward, defined) {
+            (true, _) => defined,
+            (false, 0) => {
+                let label = Primitive::LocalLabel(number, forward).to_string();
+                throw!(token, UnknownLabel(label))
+            }
+            (false, _) => defined - 1,
+        };
+        self.emit(ByteCode::LocalAddr((token.clone(), number, nth)), &token)
+    }
+
+    /// Translates the tokens of a whole line, recording where they ended up.
+    fn translate_line(&mut self, buffer: &mut Vec<Token>) -> Result<(), AssemblyError> {
+        buffer.retain(|token| !matches!(token.kind, TokenKind::Comment(_)));
+        let line = buffer.first().map(|token: &Token| token.line);
+        let start = self.address;
+        if self.preprocess(buffer)? {
+            self.translate_buffer(buffer)?;
+        } else {
+            buffer.clear();
+        }
+        // Gaps are not part of any statement
+        let is_gap = matches!(self.byte_code.last(), Some(ByteCode::Gap(_)));
+        if let Some(line) = line.filter(|_| self.address != start && !is_gap) {
+            self.source_map.push((line, start..self.address));
+        }
+        Ok(())
+    }
+
+    /// Checks that every block was closed, and resolves label addresses.
+    fn finish(self) -> Result<Output, AssemblyError> {
+        let mut data = Vec::with_capacity(self.address as usize);
+        let mut output = self.finish_into(&mut data)?;
+        output.data = data;
+        Ok(output)
+    }
+
+    /// Same as [`finish`](Self::finish), but writes the bytes after those
+    /// already in `data`, which is left as it was on error.
+    fn finish_into(mut self, data: &mut Vec<u8>) -> Result<Output, AssemblyError> {
+        if let Some(conditional) = self.conditionals.pop() {
+            throw!(conditional.token, UnterminatedConditional);
+        }
+        let instructions = std::mem::take(&mut self.instructions);
+        let options = self.options;
+        let locals: Vec<u16> = self.local_labels.values().flatten().copied().collect();
+        let base = data.len();
+        let output = self.fill_addresses(data);
+        if output.is_err() {
+            data.truncate(base);
+        }
+        let mut output = output?;
+        warn_dead_code(&mut output, &data[base..], &instructions, &locals, options);
+        output.code = instructions.into_iter().map(|(_, range)| range).collect();
+        Ok(output)
+    }
+
+    /// Keeps track of conditional blocks, returning whether the line is to be
+    /// translated.
+    fn preprocess(&mut self, buffer: &[Token]) -> Result<bool, AssemblyError> {
+        use token::Directive::*;
+        // Whether such a label would be defined is up to the very block it
+        // opens or closes
+        let labels = buffer
+            .iter()
+            .take_while(|token| matches!(token.kind, TokenKind::Label(_)))
+            .count();
+        if labels > 0
+            && matches!(
+                buffer.get(labels).map(|token| &token.kind),
+                Some(TokenKind::Directive(If | Else | Endif))
+            )
+        {
+            throw!(buffer[0].clone(), LabelOnConditional);
+        }
+        let (token, directive) = match buffer.first() {
+            Some(
+                token @ Token {
+                    kind: TokenKind::Directive(directive @ (If | Else | Endif)),
+                    ..
+                },
+            ) => (token.clone(), *directive),
+            _ => return Ok(self.conditionals.iter().all(|c| c.active)),
+        };
+        let operands = &buffer[1..];
+        let req = if directive == If { 1 } else { 0 };
+        if operands.len() > req {
+            throw!(operands[req].clone(), ExcessiveOperands(req));
+        }
+        match directive {
+            If => {
+                let condition = match operands.first().map(|operand| &operand.kind) {
+                    Some(TokenKind::Operand(Primitive::Number(value))) => *value != 0,
+                    Some(TokenKind::Operand(primitive @ Primitive::Expression(_))) => {
+                        match self.resolve(&operands[0], primitive.clone(), false)? {
+                            Primitive::Number(value) => value != 0,
+                            _ => throw!(operands[0].clone(), BadData),
+                        }
+                    }
+                    Some(TokenKind::Operand(Primitive::Label(name))) => {
+                        let define = self.options.defines.iter().find_map(|(define, &value)| {
+                            Some(value).filter(|_| define.eq_ignore_ascii_case(name))
+                        });
+                        define
+                            .or_else(|| self.constants.get(name).map(|(value, _)| *value))
+                            .unwrap_or(0)
+                            != 0
+                    }
+                    // Binary numbers, as in `.if 1b`
+                    Some(TokenKind::Operand(Primitive::LocalLabel(number, false))) => {
+                        match Primitive::local_as_number(*number, false) {
+                            Ok(Primitive::Number(value)) => value != 0,
+                            _ => throw!(operands[0].clone(), BadData),
+                        }
+                    }
+                    Some(_) => throw!(operands[0].clone(), BadData),
+                    None => {
+                        let found = 0;
+                        throw!(token, NotEnoughOperands(found, req))
                     }
+                };
+                let parent_active = self.conditionals.iter().all(|c| c.active);
+                self.conditionals.push(Conditional {
+                    token,
+                    active: parent_active && condition,
+                    parent_active,
+                    in_else: false,
+                });
+            }
+            Else => match self.conditionals.last_mut() {
+                Some(conditional) if !conditional.in_else => {
+                    conditional.in_else = true;
+                    conditional.active = conditional.parent_active && !conditional.active;
                 }
+                _ => throw!(token, UnmatchedConditional),
             },
-            Error => unreachable!("tried to parse bad token"),
+            Endif => {
+                if self.conditionals.pop().is_none() {
+                    throw!(token, UnmatchedConditional);
+                }
+            }
+            _ => unreachable!("not a conditional directive"),
         }
+        Ok(false)
     }
-    let (mnemonic_token, mnemonic) = match stmt_mnemonic {
-        Some(pair) => pair,
-        None => return Ok(()),
-    };
-    if operands_found != operands_req {
-        throw!(
-            mnemonic_token,
-            NotEnoughOperands(operands_found, operands_req)
-        )
-    }
-    let inst = Instruction::new().encode_mnemonic(mnemonic);
-    match operands {
-        // intel => dest, origin
-        [Some((dest_token, dest)), Some((origin_token, origin))] => {
-            match inst.try_encode_data_flow(&origin, &dest) {
-                Ok(inst) => unsafe {
-                    let bytes = inst.as_bytes().into_iter().map(ByteCode::Byte);
-                    byte_code.extend(bytes);
+
+    fn translate_buffer(&mut self, buffer: &mut Vec<Token>) -> Result<(), AssemblyError> {
+        use TokenKind::*;
+        // Inside the buffer we have a line of mpp assembly tokens,
+        // which is structured as:
+        // [label] [mnemonic [operands] | directive [operands]]
+        let mut operands_req = 0;
+        let mut operands_found = 0;
+        let mut operands: [Option<(Token, Primitive)>; 2] = [None, None];
+        let mut stmt_mnemonic: Option<(Token, token::Mnemonic)> = None;
+        // Where the statement ends, for pointing at missing operands
+        let mut stmt_end = 0;
+        let mut tokens = buffer.drain(..).peekable();
+        while let Some(token) = tokens.next() {
+            let token_end = token.span.end;
+            match &token.kind {
+                Label(label) => self.define_label(&token, label)?,
+                Mnemonic(mnemonic) => {
+                    if stmt_mnemonic.is_some() {
+                        throw!(token, MultipleMnemonics);
+                    }
+                    operands_req = mnemonic.operands_required();
+                    stmt_mnemonic = Some((token.clone(), *mnemonic));
+                }
+                Directive(directive) => {
+                    if stmt_mnemonic.is_some() {
+                        throw!(token, MultipleMnemonics);
+                    }
+                    let directive = *directive;
+                    return self.translate_directive(directive, token, tokens);
+                }
+                Operand(primitive) => match stmt_mnemonic {
+                    None if tokens
+                        .any(|token| matches!(token.kind, Mnemonic(_) | Directive(_))) =>
+                    {
+                        throw!(token, OperandBeforeMnemonic)
+                    }
+                    None => throw!(token, NoMnemonic),
+                    Some((_, mnemonic)) if operands_found == operands_req => {
+                        if mnemonic.is_jump() {
+                            throw!(token, ExtraJumpOperand);
+                        }
+                        throw!(token, ExcessiveOperands(operands_req));
+                    }
+                    Some((_, mnemonic)) => {
+                        // Only jumps take labels, numeric or not
+                        let keep_locals = mnemonic.operands_required() == 1;
+                        let primitive = self.resolve(&token, primitive.clone(), keep_locals)?;
+                        operands[operands_found].replace((token.clone(), primitive));
+                        operands_found += 1;
+                    }
                 },
-                Err(ErrorCode::BadOrigin) => throw!(origin_token, BadOrigin),
-                Err(ErrorCode::BadDestination) => throw!(dest_token, BadDestination),
-                Err(err) => unreachable!("unexpected data flow error: {:?}", err),
+                Comma => match stmt_mnemonic {
+                    None => throw!(token, NoMnemonic),
+                    Some((_, mnemonic)) => {
+                        if operands_req == operands_found {
+                            // Point at what should be deleted, which is the
+                            // operand after the comma if there is one
+                            let extra = tokens.peek().cloned().unwrap_or(token);
+                            if mnemonic.is_jump() {
+                                throw!(extra, ExtraJumpOperand);
+                            }
+                            throw!(extra, ExcessiveOperands(operands_req));
+                        } else if operands_found == 0 {
+                            throw!(token.clone(), UnexpectedComma);
+                        }
+                    }
+                },
+                // Comments carry nothing to translate
+                Comment(_) => continue,
+                // The lexer never sends these, but a panic is no way to find out
+                Error => {
+                    let err = TokenizingError::UnknownToken;
+                    throw!(token, Token(err))
+                }
+            }
+            stmt_end = token_end;
+        }
+        let (mnemonic_token, mnemonic) = match stmt_mnemonic {
+            Some(pair) => pair,
+            None => return Ok(()),
+        };
+        // The value operated on always comes from the accumulator, so only
+        // `al` itself may be changed in place
+        if let (true, 1, Some((dest_token, dest))) =
+            (mnemonic.is_unary(), operands_found, operands[0].clone())
+        {
+            if dest != Primitive::Accumulator {
+                let stmt = format!("{} {}, al", mnemonic, dest);
+                throw!(dest_token, UnaryOutsideAccumulator(stmt))
+            }
+            operands[1] = Some((dest_token, Primitive::Accumulator));
+            operands_found = 2;
+        }
+        if operands_found != operands_req {
+            // Only the destination of a unary mnemonic has to be written out
+            if mnemonic.is_unary() {
+                operands_req = 1;
+            }
+            let span = stmt_end..stmt_end + 1;
+            throw!(
+                Token {
+                    span,
+                    ..mnemonic_token
+                },
+                NotEnoughOperands(operands_found, operands_req)
+            )
+        }
+        if let Some(ports) = self.options.available_ports {
+            for (token, operand) in operands.iter().flatten() {
+                match operand {
+                    Primitive::Port(port) if !ports.contains(*port) => {
+                        let port = *port;
+                        throw!(token.clone(), UnavailablePort(port))
+                    }
+                    _ => (),
+                }
             }
         }
-        // Flow control takes a single label operand
-        [Some((label_dest_token, Primitive::Label(label))), None] => unsafe {
-            let bytes = inst.as_bytes().into_iter().map(ByteCode::Byte);
-            byte_code.extend(bytes);
-            byte_code.push(ByteCode::Addr((label_dest_token, label)))
-        },
-        [Some(_), None] => throw!(mnemonic_token, NoLabel),
-        [None, Some(_)] => unreachable!("primitive parsed out of order"),
-        [None, None] => (),
+        self.track_stack(mnemonic, &mnemonic_token);
+        self.track_flags(mnemonic, &mnemonic_token);
+        self.falls_through = match mnemonic {
+            token::Mnemonic::Jmp | token::Mnemonic::Ret => None,
+            _ => Some(mnemonic_token.clone()),
+        };
+        let (start, stmt_token) = (self.address, mnemonic_token.clone());
+        if let token::Mnemonic::Ja | token::Mnemonic::Jb = mnemonic {
+            let [target, _] = operands;
+            self.expand_comparison(mnemonic, mnemonic_token, target)?;
+            self.instructions.push((stmt_token, start..self.address));
+            return Ok(());
+        }
+        let isa = &self.options.isa;
+        let inst = Instruction::new().encode_mnemonic_with(mnemonic, isa);
+        match operands {
+            // Constants may only reach a port, and registers another
+            // register, through the accumulator
+            [Some((_, dest @ Primitive::Port(Port::Output(_)))), Some((_, origin @ Primitive::Number(_)))]
+            | [Some((_, dest @ Primitive::Register(_))), Some((_, origin @ Primitive::Register(_)))]
+                if mnemonic == token::Mnemonic::Mov && self.options.pseudo_instructions =>
+            {
+                if self.options.forbid_implicit_clobber {
+                    throw!(mnemonic_token, ImplicitClobber);
+                }
+                let acc = Primitive::Accumulator;
+                for (origin, dest) in [(&origin, &acc), (&acc, &dest)] {
+                    let inst = inst
+                        .try_encode_data_flow_with(origin, dest, isa)
+                        .expect("pseudo instruction expanded into a bad data flow");
+                    self.emit_instruction(inst, &mnemonic_token)?;
+                }
+                self.warnings.push(AssemblyWarning::new(
+                    mnemonic_token,
+                    WarningCode::ClobberedAccumulator,
+                ));
+            }
+            // intel => dest, origin
+            [Some((dest_token, dest)), Some((origin_token, origin))] => {
+                match inst.try_encode_data_flow_with(&origin, &dest, isa) {
+                    Ok(inst) => self.emit_instruction(inst, &mnemonic_token)?,
+                    Err(ErrorCode::ReadFromOutput) => throw!(origin_token, ReadFromOutput),
+                    Err(ErrorCode::WriteToInput) => throw!(dest_token, WriteToInput),
+                    // No decoder page holds a flow through `[al]`
+                    Err(_) if dest == Primitive::DynamicMemoryAccumulator => {
+                        throw!(dest_token, AccumulatorAddress)
+                    }
+                    Err(_) if origin == Primitive::DynamicMemoryAccumulator => {
+                        throw!(origin_token, AccumulatorAddress)
+                    }
+                    // A common mistake when coming from AT&T syntax, so check
+                    // if the flow would have been valid the other way around
+                    Err(_) if inst.try_encode_data_flow_with(&dest, &origin, isa).is_ok() => {
+                        let span = dest_token.span.start..origin_token.span.end;
+                        throw!(Token { span, ..dest_token }, SwappedOperands)
+                    }
+                    Err(ErrorCode::BadOrigin) => throw!(origin_token, BadOrigin),
+                    Err(ErrorCode::BadDestination) => throw!(dest_token, BadDestination),
+                    Err(err) => unreachable!("unexpected data flow error: {:?}", err),
+                }
+            }
+            // Flow control takes a single label operand
+            [Some((label_dest_token, Primitive::Label(label))), None] => {
+                self.emit_jump(inst, &mnemonic_token)?;
+                let addr = ByteCode::Addr((label_dest_token.clone(), label));
+                self.emit(addr, &label_dest_token)?;
+            }
+            [Some((label_dest_token, Primitive::LocalLabel(number, forward))), None] => {
+                self.emit_jump(inst, &mnemonic_token)?;
+                self.emit_local_addr(label_dest_token, number, forward)?;
+            }
+            [Some(_), None] => throw!(mnemonic_token, NoLabel),
+            [None, Some((origin_token, _))] => throw!(origin_token, OperandBeforeMnemonic),
+            [None, None] => self.emit_instruction(inst, &mnemonic_token)?,
+        }
+        self.instructions.push((stmt_token, start..self.address));
+        Ok(())
     }
-    Ok(())
-}
 
-fn fill_addresses(
-    byte_code: Vec<ByteCode>,
-    labels_idx: &HashMap<String, u16>,
-) -> Result<Vec<u8>, AssemblyError> {
-    let mut final_byte_code = Vec::with_capacity(byte_code.len());
-    for maybe_byte in byte_code {
-        match maybe_byte {
-            ByteCode::Byte(byte) => final_byte_code.push(byte),
-            ByteCode::Addr((token, label)) => {
-                if let Some(word) = labels_idx.get(&label) {
-                    let [hi, lo] = word.to_be_bytes();
-                    final_byte_code.push(hi);
-                    final_byte_code.push(lo);
-                } else {
-                    throw!(token, UnknownLabel(label))
+    /// Expands `jb label` into `jc label`, and `ja label` into `jc skip`,
+    /// `jz skip`, and `jmp label`, with `skip` right after them.
+    fn expand_comparison(
+        &mut self,
+        mnemonic: token::Mnemonic,
+        mnemonic_token: Token,
+        target: Option<(Token, Primitive)>,
+    ) -> Result<(), AssemblyError> {
+        use token::Mnemonic::*;
+        if !self.options.pseudo_instructions {
+            throw!(mnemonic_token, PseudoInstruction);
+        }
+        let isa = &self.options.isa;
+        let jump = |mnemonic| Instruction::new().encode_mnemonic_with(mnemonic, isa);
+        let (last, skips) = match mnemonic {
+            Ja => (jump(Jmp), vec![jump(Jmpc), jump(Jmpz)]),
+            _ => (jump(Jmpc), Vec::new()),
+        };
+        let len: usize = skips
+            .iter()
+            .chain([&last])
+            .map(|inst| inst.byte_len() + 2)
+            .sum();
+        let base = self.options.base_address;
+        let skip = match base
+            .checked_add(self.address)
+            .and_then(|address| address.checked_add(len as u16))
+        {
+            Some(skip) => skip,
+            None => throw!(mnemonic_token, AddressOverflow(base)),
+        };
+        for inst in skips {
+            self.emit_jump(inst, &mnemonic_token)?;
+            self.emit(ByteCode::KnownAddr(skip), &mnemonic_token)?;
+        }
+        match target {
+            Some((target_token, Primitive::Label(label))) => {
+                self.emit_jump(last, &mnemonic_token)?;
+                self.emit(ByteCode::Addr((target_token.clone(), label)), &target_token)?;
+            }
+            Some((target_token, Primitive::LocalLabel(number, forward))) => {
+                self.emit_jump(last, &mnemonic_token)?;
+                self.emit_local_addr(target_token, number, forward)?;
+            }
+            _ => throw!(mnemonic_token, NoLabel),
+        }
+        Ok(())
+    }
+
+    /// Folds constants and expressions into numbers. Local label references
+    /// are read as binary numbers unless `keep_locals` is set.
+    fn resolve(
+        &self,
+        token: &Token,
+        primitive: Primitive,
+        keep_locals: bool,
+    ) -> Result<Primitive, AssemblyError> {
+        let lookup = |name: &str| self.lookup(name);
+        match primitive {
+            Primitive::Label(name) => match lookup(&name) {
+                Some(value) => Ok(Primitive::Number(value as u8)),
+                None if self.options.accumulator_aliases && is_accumulator_alias(&name) => {
+                    Ok(Primitive::Accumulator)
+                }
+                None => Ok(Primitive::Label(name)),
+            },
+            Primitive::LocalLabel(number, forward) if !keep_locals => {
+                match Primitive::local_as_number(number, forward) {
+                    Ok(number) => self.resolve(token, number, keep_locals),
+                    Err(err) => throw!(token.clone(), Token(err)),
                 }
             }
+            Primitive::Word(_) => {
+                let err = TokenizingError::ByteOverflow;
+                throw!(token.clone(), Token(err))
+            }
+            Primitive::Expression(src) => match expression::evaluate(&src, lookup) {
+                // Negative values wrap around, as negative literals do
+                Ok(value) if (-255..=255).contains(&value) => Ok(Primitive::Number(value as u8)),
+                Ok(value) => throw!(token.clone(), NumberOutOfRange(value)),
+                Err(code) => Err(AssemblyError::new(token.clone(), code)),
+            },
+            primitive => Ok(primitive),
+        }
+    }
+
+    /// Same as [`resolve`](Self::resolve), but for operands that take a word,
+    /// such as those of `.dw`: expressions fold into a [`Primitive::Word`],
+    /// negative ones wrapping around to `0xffff` and below, and local label
+    /// references are kept.
+    fn resolve_word(
+        &self,
+        token: &Token,
+        primitive: Primitive,
+    ) -> Result<Primitive, AssemblyError> {
+        match primitive {
+            Primitive::Word(word) => Ok(Primitive::Word(word)),
+            Primitive::Expression(src) => {
+                match expression::evaluate(&src, |name| self.lookup(name)) {
+                    Ok(value) if (-0xffff..=0xffff).contains(&value) => {
+                        Ok(Primitive::Word(value as u16))
+                    }
+                    Ok(value) => throw!(token.clone(), NumberOutOfRange(value)),
+                    Err(code) => Err(AssemblyError::new(token.clone(), code)),
+                }
+            }
+            primitive => self.resolve(token, primitive, true),
+        }
+    }
+
+    /// The value of the constant `name`, or of the current address for `$`,
+    /// which includes the base address as labels do.
+    fn lookup(&self, name: &str) -> Option<i64> {
+        match name {
+            "$" => Some(self.options.base_address as i64 + self.address as i64),
+            _ => self.constants.get(name).map(|(value, _)| *value as i64),
+        }
+    }
+
+    /// Follows the stack's depth linearly through the source, warning about
+    /// pushes beyond the stack's size and routines returning with values
+    /// still pushed, or with more values popped than were pushed.
+    fn track_stack(&mut self, mnemonic: token::Mnemonic, token: &Token) {
+        use token::Mnemonic::*;
+        match mnemonic {
+            Push | Pusha => {
+                self.stack_depth += 1;
+                match self.options.stack_size {
+                    Some(size) if self.stack_depth > size as isize => self.warnings.push(
+                        AssemblyWarning::new(token.clone(), WarningCode::StackOverflow(size)),
+                    ),
+                    _ => (),
+                }
+            }
+            Pop | Popa => self.stack_depth -= 1,
+            Ret => {
+                if self.options.check_stack_balance && self.stack_depth != 0 {
+                    self.warnings.push(AssemblyWarning::new(
+                        token.clone(),
+                        WarningCode::StackImbalance(self.stack_depth),
+                    ));
+                }
+                self.stack_depth = 0;
+            }
+            // Whatever follows an unconditional jump is on another code path
+            Jmp => self.stack_depth = 0,
+            _ => (),
         }
     }
-    Ok(final_byte_code)
+
+    /// Follows which flags are set along the source, warning about conditional
+    /// jumps testing a flag no instruction since the last label has set.
+    fn track_flags(&mut self, mnemonic: token::Mnemonic, token: &Token) {
+        use token::Mnemonic::*;
+        if let Some((carry, zero)) = self.flags_set {
+            let unset = match mnemonic {
+                Jmpc | Jb | Ja if !carry => Some('C'),
+                Jmpz | Ja if !zero => Some('Z'),
+                _ => None,
+            };
+            if let Some(flag) = unset {
+                self.warnings.push(AssemblyWarning::new(
+                    token.clone(),
+                    WarningCode::UnsetFlag(flag),
+                ));
+            }
+        }
+        let (sets_carry, sets_zero) = mnemonic.affects_flags();
+        self.flags_set = match mnemonic {
+            // Routines may set any flag, and past a jump only labels lead
+            Call | Jmp | Ret => None,
+            _ => self
+                .flags_set
+                .map(|(carry, zero)| (carry || sets_carry, zero || sets_zero)),
+        };
+    }
+
+    /// Warns about the instruction right before the data about to be emitted,
+    /// if execution may run past it and into the data.
+    fn warn_fall_through(&mut self) {
+        if let Some(token) = self.falls_through.take() {
+            self.warnings.push(AssemblyWarning::new(
+                token,
+                WarningCode::FallThroughIntoData,
+            ));
+        }
+    }
+
+    fn translate_directive(
+        &mut self,
+        directive: token::Directive,
+        directive_token: Token,
+        tokens: impl Iterator<Item = Token>,
+    ) -> Result<(), AssemblyError> {
+        use TokenKind::*;
+        // Directives take any amount of comma separated operands
+        let mut operands = Vec::new();
+        let mut trailing_comma = None;
+        for token in tokens {
+            match &token.kind {
+                Operand(_) if !operands.is_empty() && trailing_comma.is_none() => {
+                    throw!(token, MissingComma)
+                }
+                Operand(primitive) => {
+                    // The name of a constant being defined is kept as is
+                    let primitive = if directive == token::Directive::Equ && operands.is_empty() {
+                        primitive.clone()
+                    } else if matches!(directive, token::Directive::Dw | token::Directive::Org) {
+                        self.resolve_word(&token, primitive.clone())?
+                    } else {
+                        let keep_locals = directive == token::Directive::JmpTable;
+                        self.resolve(&token, primitive.clone(), keep_locals)?
+                    };
+                    operands.push((token, primitive));
+                    trailing_comma = None;
+                }
+                Comma if operands.is_empty() || trailing_comma.is_some() => {
+                    throw!(token, UnexpectedComma)
+                }
+                Comma => trailing_comma = Some(token),
+                Mnemonic(_) | Directive(_) => throw!(token, MultipleMnemonics),
+                Label(_) => throw!(token, BadData),
+                Comment(_) => continue,
+                // The lexer never sends these, but a panic is no way to find out
+                Error => {
+                    let err = TokenizingError::UnknownToken;
+                    throw!(token, Token(err))
+                }
+            }
+        }
+        if let Some(comma) = trailing_comma {
+            throw!(comma, UnexpectedComma);
+        }
+        if operands.is_empty() {
+            let span = directive_token.span.end..directive_token.span.end + 1;
+            return Err(AssemblyError::new(
+                Token {
+                    span,
+                    ..directive_token
+                },
+                ErrorCode::NotEnoughOperands(0, 1),
+            ));
+        }
+        match directive {
+            token::Directive::Db => {
+                self.warn_fall_through();
+                for (token, primitive) in operands {
+                    match primitive {
+                        Primitive::Number(byte) => self.emit(ByteCode::Byte(byte), &token)?,
+                        Primitive::Range(start, end) => self.emit_bytes(start..=end, &token)?,
+                        _ => throw!(token, BadData),
+                    }
+                }
+            }
+            token::Directive::Org => {
+                if operands.len() > 1 {
+                    let req = 1;
+                    throw!(operands.swap_remove(1).0, ExcessiveOperands(req));
+                }
+                let (token, origin) = match operands.pop() {
+                    Some((token, Primitive::Number(origin))) => (token, origin as u16),
+                    Some((token, Primitive::Word(origin))) => (token, origin),
+                    Some((token, _)) => throw!(token, BadData),
+                    None => unreachable!("directive without operands"),
+                };
+                // Nothing was emitted since an `.org` to this very address
+                if origin == self.address && self.last_origin == Some(origin) {
+                    self.warnings.push(AssemblyWarning::new(
+                        directive_token,
+                        WarningCode::RedundantOrigin(origin),
+                    ));
+                }
+                self.last_origin = Some(origin);
+                if origin < self.address {
+                    let (origin, address) = (origin as usize, self.address as usize);
+                    throw!(token, BackwardOrigin(origin, address));
+                } else if origin > self.address {
+                    let gap = ByteCode::Gap((token.clone(), origin - self.address));
+                    self.emit(gap, &token)?;
+                }
+                // Whatever lies past the gap is not reached by running on
+                self.falls_through = None;
+                self.flags_set = None;
+            }
+            token::Directive::If | token::Directive::Else | token::Directive::Endif => {
+                // Conditionals preceded by labels are rejected by `preprocess`
+                unreachable!("conditional directive left for translation")
+            }
+            token::Directive::Assert => {
+                if operands.len() > 2 {
+                    let req = 2;
+                    throw!(operands.swap_remove(2).0, ExcessiveOperands(req));
+                }
+                let msg = match operands.pop() {
+                    Some((_, Primitive::Text(msg))) => Some(msg),
+                    Some(operand) => {
+                        operands.push(operand);
+                        None
+                    }
+                    None => unreachable!("directive without operands"),
+                };
+                match operands.pop() {
+                    Some((_, Primitive::Number(condition))) if operands.is_empty() => {
+                        if condition == 0 {
+                            throw!(directive_token, AssertionFailed(msg));
+                        }
+                    }
+                    Some((token, _)) => throw!(token, BadData),
+                    None => {
+                        let (found, req) = (1, 2);
+                        throw!(directive_token, NotEnoughOperands(found, req))
+                    }
+                }
+            }
+            token::Directive::Equ => {
+                if operands.len() > 2 {
+                    let req = 2;
+                    throw!(operands.swap_remove(2).0, ExcessiveOperands(req));
+                }
+                let (value_token, value) = match operands.pop() {
+                    Some((token, Primitive::Number(value))) if !operands.is_empty() => {
+                        (token, value)
+                    }
+                    Some((token, _)) if !operands.is_empty() => throw!(token, BadData),
+                    _ => {
+                        let (found, req) = (1, 2);
+                        throw!(directive_token, NotEnoughOperands(found, req))
+                    }
+                };
+                match operands.pop() {
+                    Some((token, Primitive::Label(name))) => {
+                        if let Some((_, first)) = self.constants.get(&name) {
+                            let line = first.line;
+                            throw!(token, RedefinedLabel(line));
+                        }
+                        self.constants.insert(name, (value, token));
+                    }
+                    _ => throw!(value_token, BadData),
+                }
+            }
+            token::Directive::Dw => {
+                self.warn_fall_through();
+                for (token, primitive) in operands {
+                    match primitive {
+                        Primitive::Number(byte) => self.emit_bytes([0, byte], &token)?,
+                        Primitive::Word(word) => self.emit_bytes(word.to_be_bytes(), &token)?,
+                        Primitive::Label(label) => {
+                            self.emit(ByteCode::Addr((token.clone(), label)), &token)?
+                        }
+                        Primitive::LocalLabel(number, forward) => {
+                            self.emit_local_addr(token, number, forward)?
+                        }
+                        _ => throw!(token, BadData),
+                    }
+                }
+            }
+            token::Directive::JmpTable => {
+                self.warn_fall_through();
+                for (token, primitive) in operands {
+                    match primitive {
+                        Primitive::Label(label) => {
+                            self.emit(ByteCode::Addr((token.clone(), label)), &token)?
+                        }
+                        Primitive::LocalLabel(number, forward) => {
+                            self.emit_local_addr(token, number, forward)?
+                        }
+                        _ => throw!(token, NoLabel),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_addresses(self, final_byte_code: &mut Vec<u8>) -> Result<Output, AssemblyError> {
+        // Where the byte code starts, as `final_byte_code` may not be empty
+        let base = final_byte_code.len();
+        let mut gaps = Vec::new();
+        let mut relocations = Vec::new();
+        let mut segments = Vec::new();
+        let mut segment_start = 0;
+        for maybe_byte in self.byte_code {
+            match maybe_byte {
+                ByteCode::Gap((token, len)) => {
+                    let start = (final_byte_code.len() - base) as u16;
+                    if start != segment_start {
+                        segments.push(Segment {
+                            start: segment_start,
+                            len: (start - segment_start) as usize,
+                        });
+                    }
+                    segment_start = match start.checked_add(len) {
+                        Some(end) => end,
+                        None => {
+                            let base = self.options.base_address;
+                            throw!(token, AddressOverflow(base))
+                        }
+                    };
+                    let fill = match self.options.gaps {
+                        Gaps::Fill(byte) => byte,
+                        Gaps::Sparse => {
+                            gaps.push(start..segment_start);
+                            0
+                        }
+                    };
+                    final_byte_code.resize(final_byte_code.len() + len as usize, fill);
+                }
+                ByteCode::Byte(byte) => final_byte_code.push(byte),
+                ByteCode::Addr((token, label)) => {
+                    if let Some((word, _)) = self.labels_idx.get(&label) {
+                        relocations.push(final_byte_code.len() - base);
+                        let [hi, lo] = word.to_be_bytes();
+                        final_byte_code.push(hi);
+                        final_byte_code.push(lo);
+                    } else {
+                        throw!(token, UnknownLabel(label))
+                    }
+                }
+                ByteCode::LocalAddr((token, number, nth)) => {
+                    let address = self.local_labels.get(&number).and_then(|all| all.get(nth));
+                    match address {
+                        Some(&word) => {
+                            relocations.push(final_byte_code.len() - base);
+                            final_byte_code.extend_from_slice(&word.to_be_bytes());
+                        }
+                        None => {
+                            let label = Primitive::LocalLabel(number, true).to_string();
+                            throw!(token, UnknownLabel(label))
+                        }
+                    }
+                }
+                ByteCode::KnownAddr(word) => {
+                    relocations.push(final_byte_code.len() - base);
+                    final_byte_code.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+        // Jumps were built before their target was known
+        let instructions = self
+            .built
+            .into_iter()
+            .map(|(address, inst, awaits_target)| {
+                if !awaits_target {
+                    return inst;
+                }
+                let at = base + address as usize + inst.byte_len();
+                let target = [final_byte_code[at], final_byte_code[at + 1]];
+                inst.encode_ram(u16::from_be_bytes(target))
+            })
+            .collect();
+        let end = (final_byte_code.len() - base) as u16;
+        if end != segment_start {
+            segments.push(Segment {
+                start: segment_start,
+                len: (end - segment_start) as usize,
+            });
+        }
+        Ok(Output {
+            data: Vec::new(),
+            source_map: self.source_map,
+            labels: self
+                .labels_idx
+                .into_iter()
+                .map(|(label, (address, _))| (label, address))
+                .collect(),
+            warnings: self.warnings,
+            gaps,
+            relocations,
+            segments,
+            code: Vec::new(),
+            instructions,
+        })
+    }
+}
+
+/// Warns about every instruction that follows an unconditional `jmp` or `ret`
+/// without any label, numeric or not, to reach it through, as read back from
+/// the byte code.
+fn warn_dead_code(
+    output: &mut Output,
+    data: &[u8],
+    instructions: &[(Token, Range<u16>)],
+    local_labels: &[u16],
+    options: &AssembleOptions,
+) {
+    let targets: HashSet<u16> = output
+        .labels
+        .values()
+        .chain(local_labels)
+        .map(|address| address.wrapping_sub(options.base_address))
+        .collect();
+    let mut unconditional_end = None;
+    for (token, range) in instructions {
+        let start = range.start;
+        if unconditional_end == Some(start) && !targets.contains(&start) {
+            output.warnings.push(AssemblyWarning::new(
+                token.clone(),
+                WarningCode::UnreachableCode,
+            ));
+        }
+        let decoded = disassembly::decode(&data[start as usize..], &options.isa);
+        unconditional_end = match decoded {
+            Some((DecodedKind::Instruction(token::Mnemonic::Jmp, _), _))
+            | Some((DecodedKind::Instruction(token::Mnemonic::Ret, _), _)) => Some(range.end),
+            _ => None,
+        };
+    }
+}
+
+/// Feeds an already tokenized stream to the parser, skipping the lexer.
+#[cfg(test)]
+pub(crate) fn parse_tokens(tokens: Vec<Result<Token, EOL>>) -> Result<Vec<u8>, AssemblyError> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for token in tokens {
+        sender.send(token).expect("parser stopped unexpectedly");
+    }
+    drop(sender);
+    eval(receiver, &AssembleOptions::default()).map(|output| output.data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token(kind: TokenKind) -> Result<Token, EOL> {
+        Ok(Token {
+            kind,
+            span: 0..0,
+            line: 1,
+        })
+    }
+
+    #[test]
+    fn test_unknown_label() {
+        let tokens = vec![
+            token(TokenKind::Mnemonic(token::Mnemonic::Jmp)),
+            token(TokenKind::Operand(Primitive::Label("nowhere".into()))),
+            Err(EOL),
+        ];
+        let err = parse_tokens(tokens).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::UnknownLabel(_)));
+    }
+
+    #[test]
+    fn test_comments_left_in() {
+        let options = AssembleOptions::default();
+        let mut parser = Parser::new(&options);
+        let mut lexer = crate::Lexer::new().keep_comments(true);
+        let tokens = lexer.scan("mov al ; no origin\n.db 1h ; one").unwrap();
+        let mut lines = tokens.split(Result::is_err).map(|line| {
+            let buffer = line.iter().filter_map(|token| token.as_ref().ok());
+            buffer.cloned().collect::<Vec<_>>()
+        });
+        let err = parser
+            .translate_buffer(&mut lines.next().unwrap())
+            .unwrap_err();
+        assert!(matches!(err.code, ErrorCode::NotEnoughOperands(1, 2)));
+        // The missing operand goes right after the last one, not the comment
+        assert_eq!(err.token.span, 6..7);
+        parser.translate_buffer(&mut lines.next().unwrap()).unwrap();
+        assert_eq!(parser.finish().unwrap().data, [1]);
+    }
+
+    #[test]
+    fn test_stack_balance() {
+        let options = AssembleOptions {
+            check_stack_balance: true,
+            stack_size: Some(1),
+            ..AssembleOptions::default()
+        };
+        let (sender, receiver) = std::sync::mpsc::channel();
+        crate::lexer::scan("f: pusha\npusha\npopa\nret\ng: popa\nret", sender).unwrap();
+        let output = eval(receiver, &options).unwrap();
+        assert!(matches!(
+            output.warnings.as_slice(),
+            [
+                AssemblyWarning {
+                    code: WarningCode::StackOverflow(1),
+                    ..
+                },
+                AssemblyWarning {
+                    code: WarningCode::StackImbalance(1),
+                    ..
+                },
+                AssemblyWarning {
+                    code: WarningCode::StackImbalance(-1),
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_statement_assembler() {
+        let src = ["start: .db 1h", "jmp later", "later: jmp start"];
+        let options = AssembleOptions::default();
+        let mut assembler = StatementAssembler::new(&options);
+        assert_eq!(assembler.feed(src[0]).unwrap(), vec![1]);
+        let jmp = assembler.feed(src[1]).unwrap();
+        assert_eq!(jmp[jmp.len() - 2..], [0, 0]);
+        let jmp = assembler.feed(src[2]).unwrap();
+        assert_eq!(jmp[jmp.len() - 2..], [0, 0]);
+        assert!(assembler.feed("jmp").is_err());
+        assert_eq!(assembler.symbols(), [("start", 0), ("later", 5)]);
+        assert_eq!(assembler.bytes()[..6], [1, 7, 0b000_00_011, 0, 5, 7]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        lexer::scan(&src.join("\n"), sender).unwrap();
+        let output = eval(receiver, &options).unwrap();
+        assert_eq!(assembler.finalize().unwrap(), output.data);
+    }
+
+    #[test]
+    fn test_statement_assembler_retry() {
+        let options = AssembleOptions::default();
+        let mut assembler = StatementAssembler::new(&options);
+        assert!(assembler.feed("a: mov al, @").is_err());
+        assert_eq!(assembler.feed("a: ret").unwrap(), [7, 7, 0]);
+        // The label is defined before the missing operand is found
+        let err = assembler.feed("b: mov cl").unwrap_err();
+        assert!(matches!(err.code, ErrorCode::NotEnoughOperands(1, 2)));
+        assert_eq!(
+            assembler.feed("b: mov al, 1h").unwrap(),
+            [7, 0b110_00_000, 1]
+        );
+        assert_eq!(assembler.symbols(), [("a", 0), ("b", 3)]);
+        assert_eq!(assembler.bytes(), [7, 7, 0, 7, 0b110_00_000, 1]);
+    }
+
+    #[test]
+    fn test_missing_operand_span() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        lexer::scan("mov al ; no origin", sender).unwrap();
+        let err = eval(receiver, &AssembleOptions::default()).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::NotEnoughOperands(1, 2)));
+        assert_eq!(err.token.span, 6..7);
+    }
+
+    #[test]
+    fn test_bad_token() {
+        let err = parse_tokens(vec![token(TokenKind::Error), Err(EOL)]).unwrap_err();
+        assert!(matches!(
+            err.code,
+            ErrorCode::Token(TokenizingError::UnknownToken)
+        ));
+    }
 }
@@ -0,0 +1,11 @@
+use crate::Assembly;
+use wasm_bindgen::prelude::*;
+
+/// Assembles `src` into its byte code, or the rendered error message.
+#[wasm_bindgen]
+pub fn assemble_wasm(src: &str) -> Result<Vec<u8>, JsValue> {
+    match Assembly::assemble_sync(src) {
+        Ok(assembly) => Ok(assembly.as_byte_code().to_vec()),
+        Err(err) => Err(JsValue::from_str(&err.render(src, &"input.asm", None))),
+    }
+}